@@ -4,6 +4,7 @@
 #include <Wire.h>
 #include <U8g2lib.h>
 #include <EEPROM.h>
+#include <ArduinoJson.h>
 #include "animation_data_1.h"
 #include "animation_data_2.h"
 #include "animation_data_3.h"
@@ -21,6 +22,8 @@ int currentWifiIndex = 0;
 const char* websocket_host = "voice-assistant-z07p.onrender.com";
 const int websocket_port = 443;
 const char* websocket_path = "/ws";
+// Токен устройства от администратора сервера (POST /api/admin/devices), пусто - сервер без проверки доступа
+const char* device_token = "";
 
 #define I2S_WS 25
 #define I2S_SD 32
@@ -39,8 +42,18 @@ bool isConnected = false;
 bool isRecording = false;
 bool gotResponse = false;
 String lastResponse = "";
+String sessionId = "";  // Сессия на сервере: история разговора переживает переподключение
 int scrollOffset = 0;
 int totalLines = 0;
+// Страницы ответа, размеченные сервером (0 - ответ пришёл обычным текстом)
+int pageIndex = 0;
+int pageTotal = 0;
+
+// true - сервер сам рисует страницы картинками 128x64 (pages:bitmap), формулы
+// и символы, которых нет в шрифте u8g2, выглядят как надо
+#define SERVER_BITMAPS false
+uint8_t pageBitmap[128 * 64 / 8];
+bool hasBitmap = false;
 
 #define MAX_CHARS_PER_LINE 21
 #define VISIBLE_LINES 3
@@ -363,8 +376,42 @@ int splitTextToLines(String text) {
     return lineCount;
 }
 
+// page:{"index":0,"total":3,"lines":[...]} - строки уже перенесены сервером
+int parsePage(String json) {
+    JsonDocument doc;
+    if (deserializeJson(doc, json)) {
+        pageTotal = 0;
+        return 0;
+    }
+    pageIndex = doc["index"] | 0;
+    pageTotal = doc["total"] | 0;
+
+    int lineCount = 0;
+    for (JsonVariant line : doc["lines"].as<JsonArray>()) {
+        if (lineCount >= 99) break;
+        lines[lineCount++] = line.as<String>();
+    }
+    return lineCount;
+}
+
+int splitResponse(String response) {
+    if (response.startsWith("page:")) {
+        return parsePage(response.substring(5));
+    }
+    pageTotal = 0;
+    hasBitmap = false;
+    return splitTextToLines(response);
+}
+
 void showResponse() {
     u8g2.clearBuffer();
+    
+    if (hasBitmap) {
+        u8g2.drawXBM(0, 0, 128, 64, pageBitmap);
+        u8g2.sendBuffer();
+        return;
+    }
+    
     u8g2.setFont(u8g2_font_6x13_t_cyrillic);
     
     // Показываем 3 строки из 99 возможных
@@ -372,6 +419,13 @@ void showResponse() {
         u8g2.drawUTF8(0, 14 + i * 14, lines[scrollOffset + i].c_str());
     }
     
+    // Номер страницы от сервера
+    if (pageTotal > 1) {
+        String indicator = String(pageIndex + 1) + "/" + String(pageTotal);
+        u8g2.drawUTF8(96, 56, indicator.c_str());
+        u8g2.drawBox(127, 5 + (50 * pageIndex) / (pageTotal - 1), 1, 4);
+    }
+    
     // Индикатор прокрутки
     if (totalLines > VISIBLE_LINES) {
         String indicator = String(scrollOffset + 1) + "/" + String(totalLines - VISIBLE_LINES + 1);
@@ -396,6 +450,21 @@ void showResponse() {
     u8g2.sendBuffer();
 }
 
+// Следующая страница с сервера или прокрутка строк, перенесённых на устройстве
+void scrollResponse() {
+    if (pageTotal > 1) {
+        webSocket.sendTXT("next_page");
+        return;
+    }
+    if (totalLines <= VISIBLE_LINES) return;
+    
+    scrollOffset++;
+    if (scrollOffset > totalLines - VISIBLE_LINES) {
+        scrollOffset = 0;
+    }
+    showResponse();
+}
+
 void showAnimation() {
     u8g2.clearBuffer();
     const uint8_t* frame_data = animations[currentAnimation].frames + (currentFrame * animations[currentAnimation].frame_size);
@@ -432,6 +501,15 @@ void webSocketEvent(WStype_t type, uint8_t * payload, size_t length) {
             Serial.println("WebSocket Connected");
             isConnected = true;
             gotResponse = false;
+            // Под MAC-адресом сервер хранит разговоры устройства; отправляем его
+            // до session:, иначе сервер не отдаст сессию этого устройства
+            webSocket.sendTXT("device:" + WiFi.macAddress());
+            // Продолжаем прежний разговор (пустой id - новая сессия)
+            webSocket.sendTXT("session:" + sessionId);
+            // Сообщаем серверу параметры экрана
+            webSocket.sendTXT("profile:ssd1306");
+            // Ответы приходят уже разбитыми на страницы под этот экран
+            webSocket.sendTXT(SERVER_BITMAPS ? "pages:bitmap" : "pages:on");
             currentState = STATE_READY;
             if (lastResponse.length() == 0) {
                 showText("Сервер ОНЛАЙН!", connectedSSID.c_str(), "", "Зажми и говори");
@@ -443,8 +521,34 @@ void webSocketEvent(WStype_t type, uint8_t * payload, size_t length) {
             
             String response = String((char*)payload);
             
-            // Проверяем команду перехода в режим Морзе
-            if (response.indexOf("/morse") >= 0 || response.indexOf("/morze") >= 0) {
+            if (response.startsWith("session:")) {
+                sessionId = response.substring(8);
+                Serial.println("Сессия: " + sessionId);
+                return;
+            }
+            
+            // Служебные сообщения сервера - не показываем на экране
+            if (response.startsWith("device:") || response.startsWith("profile:") || response.startsWith("pages:")) {
+                return;
+            }
+            
+            // Заголовок страницы-картинки, сама картинка придёт бинарным кадром
+            if (response.startsWith("bitmap:")) {
+                JsonDocument doc;
+                if (!deserializeJson(doc, response.substring(7))) {
+                    pageIndex = doc["index"] | 0;
+                    pageTotal = doc["total"] | 0;
+                }
+                return;
+            }
+            
+            // Ошибка: error:текст для экрана
+            if (response.startsWith("error:")) {
+                response = response.substring(6);
+            }
+            
+            // Переход в режим Морзе (и тренировку): сервер присылает /morse после подтверждения
+            if (response == "/morse") {
                 currentState = STATE_MORSE;
                 morseCode = "";
                 morseDisplay = "";
@@ -461,16 +565,32 @@ void webSocketEvent(WStype_t type, uint8_t * payload, size_t length) {
             if (currentState == STATE_MORSE) {
                 morseInputMode = false;  // Переходим в режим просмотра
                 scrollOffset = 0;
-                totalLines = splitTextToLines(lastResponse);
+                totalLines = splitResponse(lastResponse);
                 showResponse();
             } else {
                 currentState = STATE_READY;
                 scrollOffset = 0;
-                totalLines = splitTextToLines(lastResponse);
+                totalLines = splitResponse(lastResponse);
                 showResponse();
             }
             break;
         }
+        
+        case WStype_BIN:
+            if (length == sizeof(pageBitmap)) {
+                memcpy(pageBitmap, payload, length);
+                hasBitmap = true;
+                gotResponse = true;
+                scrollOffset = 0;
+                totalLines = 0;
+                if (currentState == STATE_MORSE) {
+                    morseInputMode = false;
+                } else {
+                    currentState = STATE_READY;
+                }
+                showResponse();
+            }
+            break;
     }
 }
 
@@ -603,6 +723,9 @@ void loop() {
                 if (tryConnectWifi(currentWifiIndex)) {
                     showText("WiFi OK!", connectedSSID.c_str(), "", "Подключение к серверу");
                     delay(1000);
+                    if (strlen(device_token) > 0) {
+                        webSocket.setExtraHeaders((String("Authorization: Bearer ") + device_token).c_str());
+                    }
                     webSocket.beginSSL(websocket_host, websocket_port, websocket_path);
                     webSocket.onEvent(webSocketEvent);
                     webSocket.setReconnectInterval(5000);
@@ -694,13 +817,9 @@ void loop() {
                     morseDisplay = "";
                     showMorse();
                     Serial.println("Новый ввод Морзе");
-                } else if (pressDuration < SHORT_PRESS_TIME && totalLines > VISIBLE_LINES) {
+                } else if (pressDuration < SHORT_PRESS_TIME) {
                     // Короткое нажатие - прокрутка
-                    scrollOffset++;
-                    if (scrollOffset > totalLines - VISIBLE_LINES) {
-                        scrollOffset = 0;
-                    }
-                    showResponse();
+                    scrollResponse();
                 }
             }
         }
@@ -718,12 +837,13 @@ void loop() {
         unsigned long pressDuration = millis() - buttonPressTime;
         buttonWasPressed = false;
         
-        if (pressDuration < SHORT_PRESS_TIME && totalLines > VISIBLE_LINES) {
-            scrollOffset++;
-            if (scrollOffset > totalLines - VISIBLE_LINES) {
-                scrollOffset = 0;
+        if (pressDuration < SHORT_PRESS_TIME) {
+            if (currentState == STATE_PROCESSING) {
+                // Короткое нажатие во время обработки - отменить запрос
+                webSocket.sendTXT("cancel");
+            } else {
+                scrollResponse();
             }
-            showResponse();
         }
     }
     
@@ -735,6 +855,8 @@ void loop() {
             gotResponse = false;
             scrollOffset = 0;
             totalLines = 0;
+            pageTotal = 0;
+            hasBitmap = false;
             Serial.println("Recording started");
             showText("Запись...", "", "Говори!");
         }
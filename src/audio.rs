@@ -1,11 +1,23 @@
 use anyhow::Result;
 use hound::{WavSpec, WavWriter};
+use serde::Serialize;
 use std::path::Path;
 
+pub const SAMPLE_RATE: u32 = 16000;
+
+// Окно анализа 20 мс
+const FRAME_SAMPLES: usize = SAMPLE_RATE as usize / 50;
+const CLIP_THRESHOLD: i32 = 32000;
+
+// Пороги, при которых запись не отправляется в Whisper
+const MIN_RMS_DBFS: f32 = -45.0;
+const MIN_SPEECH_SECS: f32 = 0.3;
+const MAX_CLIPPING_RATIO: f32 = 0.02;
+
 pub fn save_raw_as_wav(raw_data: &[u8], filename: &Path) -> Result<()> {
     let spec = WavSpec {
         channels: 1,           // моно
-        sample_rate: 16000,    // 16kHz
+        sample_rate: SAMPLE_RATE, // 16kHz
         bits_per_sample: 16,   // 16 бит
         sample_format: hound::SampleFormat::Int,
     };
@@ -19,3 +31,180 @@ pub fn save_raw_as_wav(raw_data: &[u8], filename: &Path) -> Result<()> {
     writer.finalize()?;
     Ok(())
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioVerdict {
+    Ok,
+    TooQuiet,
+    Clipped,
+}
+
+impl AudioVerdict {
+    pub fn user_message(&self) -> Option<&'static str> {
+        match self {
+            AudioVerdict::Ok => None,
+            AudioVerdict::TooQuiet => Some("Не расслышал, говорите громче"),
+            AudioVerdict::Clipped => Some("Запись искажена, говорите тише или дальше от микрофона"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AudioStats {
+    pub rms: f32,
+    pub rms_dbfs: f32,
+    pub peak: f32,
+    pub clipping_ratio: f32,
+    pub snr_db: f32,
+    pub duration_secs: f32,
+    pub speech_secs: f32,
+    pub verdict: AudioVerdict,
+}
+
+/// Считает уровень, пики, клиппинг и оценку SNR для сырого PCM 16 бит.
+pub fn analyze_audio(raw_data: &[u8]) -> AudioStats {
+    let samples: Vec<i16> = raw_data
+        .chunks_exact(2)
+        .map(|chunk| i16::from_le_bytes([chunk[0], chunk[1]]))
+        .collect();
+
+    let duration_secs = samples.len() as f32 / SAMPLE_RATE as f32;
+    if samples.is_empty() {
+        return AudioStats {
+            rms: 0.0,
+            rms_dbfs: to_dbfs(0.0),
+            peak: 0.0,
+            clipping_ratio: 0.0,
+            snr_db: 0.0,
+            duration_secs,
+            speech_secs: 0.0,
+            verdict: AudioVerdict::TooQuiet,
+        };
+    }
+
+    let mut sum_squares = 0.0f64;
+    let mut peak = 0i32;
+    let mut clipped = 0usize;
+    for &sample in &samples {
+        let value = (sample as i32).abs();
+        sum_squares += (sample as f64) * (sample as f64);
+        peak = peak.max(value);
+        if value >= CLIP_THRESHOLD {
+            clipped += 1;
+        }
+    }
+
+    let rms = ((sum_squares / samples.len() as f64).sqrt() / 32768.0) as f32;
+    let peak = peak as f32 / 32768.0;
+    let clipping_ratio = clipped as f32 / samples.len() as f32;
+
+    // Шум оцениваем по тихим окнам, сигнал - по громким
+    let mut frame_levels: Vec<f32> = samples
+        .chunks(FRAME_SAMPLES)
+        .map(|frame| {
+            let energy: f64 = frame.iter().map(|&s| (s as f64) * (s as f64)).sum();
+            ((energy / frame.len() as f64).sqrt() / 32768.0) as f32
+        })
+        .collect();
+    frame_levels.sort_by(|a, b| a.total_cmp(b));
+
+    let noise = percentile(&frame_levels, 0.1).max(1e-5);
+    let signal = percentile(&frame_levels, 0.9).max(1e-5);
+    let snr_db = 20.0 * (signal / noise).log10();
+
+    let speech_threshold = (noise * 2.0).max(10f32.powf(MIN_RMS_DBFS / 20.0));
+    let speech_frames = frame_levels.iter().filter(|&&level| level > speech_threshold).count();
+    let speech_secs = (speech_frames * FRAME_SAMPLES) as f32 / SAMPLE_RATE as f32;
+
+    let rms_dbfs = to_dbfs(rms);
+    let verdict = if clipping_ratio > MAX_CLIPPING_RATIO {
+        AudioVerdict::Clipped
+    } else if rms_dbfs < MIN_RMS_DBFS || speech_secs < MIN_SPEECH_SECS {
+        AudioVerdict::TooQuiet
+    } else {
+        AudioVerdict::Ok
+    };
+
+    AudioStats {
+        rms,
+        rms_dbfs,
+        peak,
+        clipping_ratio,
+        snr_db,
+        duration_secs,
+        speech_secs: speech_secs.min(duration_secs),
+        verdict,
+    }
+}
+
+fn to_dbfs(level: f32) -> f32 {
+    20.0 * level.max(1e-5).log10()
+}
+
+fn percentile(sorted: &[f32], p: f32) -> f32 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let index = ((sorted.len() - 1) as f32 * p).round() as usize;
+    sorted[index]
+}
+
+/// Накопленная статистика по всем записям для `/api/status`.
+#[derive(Default)]
+pub struct AudioAggregate {
+    utterances: u64,
+    too_quiet: u64,
+    clipped: u64,
+    sum_rms_dbfs: f64,
+    sum_peak: f64,
+    sum_clipping_ratio: f64,
+    sum_snr_db: f64,
+    sum_duration_secs: f64,
+    sum_speech_secs: f64,
+}
+
+#[derive(Serialize)]
+pub struct AudioSummary {
+    pub utterances: u64,
+    pub too_quiet: u64,
+    pub clipped: u64,
+    pub avg_rms_dbfs: f64,
+    pub avg_peak: f64,
+    pub avg_clipping_ratio: f64,
+    pub avg_snr_db: f64,
+    pub avg_duration_secs: f64,
+    pub avg_speech_secs: f64,
+}
+
+impl AudioAggregate {
+    pub fn record(&mut self, stats: &AudioStats) {
+        self.utterances += 1;
+        match stats.verdict {
+            AudioVerdict::TooQuiet => self.too_quiet += 1,
+            AudioVerdict::Clipped => self.clipped += 1,
+            AudioVerdict::Ok => {}
+        }
+        self.sum_rms_dbfs += stats.rms_dbfs as f64;
+        self.sum_peak += stats.peak as f64;
+        self.sum_clipping_ratio += stats.clipping_ratio as f64;
+        self.sum_snr_db += stats.snr_db as f64;
+        self.sum_duration_secs += stats.duration_secs as f64;
+        self.sum_speech_secs += stats.speech_secs as f64;
+    }
+
+    pub fn summary(&self) -> AudioSummary {
+        let n = self.utterances.max(1) as f64;
+        AudioSummary {
+            utterances: self.utterances,
+            too_quiet: self.too_quiet,
+            clipped: self.clipped,
+            avg_rms_dbfs: self.sum_rms_dbfs / n,
+            avg_peak: self.sum_peak / n,
+            avg_clipping_ratio: self.sum_clipping_ratio / n,
+            avg_snr_db: self.sum_snr_db / n,
+            avg_duration_secs: self.sum_duration_secs / n,
+            avg_speech_secs: self.sum_speech_secs / n,
+        }
+    }
+}
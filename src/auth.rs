@@ -0,0 +1,178 @@
+use axum::http::{header, HeaderMap};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    env,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Доступ к серверу. Включается переменной `ADMIN_TOKEN`: тогда `/ws` пускает только
+/// зарегистрированные устройства с токеном, а `/api/sessions` и `/api/admin` - только администратора.
+/// Без неё сервер открыт, как раньше.
+pub struct Auth {
+    admin_token_hash: Option<String>,
+}
+
+impl Auth {
+    pub fn from_env() -> Self {
+        let admin_token_hash = env::var("ADMIN_TOKEN")
+            .ok()
+            .filter(|token| !token.trim().is_empty())
+            .map(|token| hash_token(token.trim()));
+        match admin_token_hash {
+            Some(_) => info!("Доступ к /ws только по токенам устройств"),
+            None => warn!("ADMIN_TOKEN не задан: /ws открыт для всех"),
+        }
+        Self { admin_token_hash }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.admin_token_hash.is_some()
+    }
+
+    /// Запрос с `Authorization: Bearer <ADMIN_TOKEN>`.
+    pub fn is_admin(&self, headers: &HeaderMap) -> bool {
+        match (&self.admin_token_hash, request_token(headers, None)) {
+            (Some(admin), Some(token)) => *admin == hash_token(token),
+            _ => false,
+        }
+    }
+}
+
+/// Токен из заголовка `Authorization: Bearer ...` или параметра `?token=`
+/// (браузерный WebSocket заголовки задавать не умеет).
+pub fn request_token<'a>(headers: &'a HeaderMap, query: Option<&'a str>) -> Option<&'a str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or(query)
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+}
+
+/// Хранится и сравнивается только хеш токена.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Случайный токен из 64 hex-символов.
+pub fn new_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// Открытые подключения устройств: отзыв или перевыпуск токена закрывает их,
+/// а не только не пускает новые.
+#[derive(Default)]
+pub struct LiveConnections {
+    next_id: AtomicU64,
+    devices: Mutex<HashMap<String, HashMap<u64, Arc<Notify>>>>,
+}
+
+impl LiveConnections {
+    pub fn register(self: &Arc<Self>, device: &str) -> LiveConnection {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let closed = Arc::new(Notify::new());
+        self.devices
+            .lock()
+            .unwrap()
+            .entry(device.to_string())
+            .or_default()
+            .insert(id, Arc::clone(&closed));
+        LiveConnection {
+            registry: Arc::clone(self),
+            device: device.to_string(),
+            id,
+            closed,
+        }
+    }
+
+    /// Просит закрыться все подключения устройства, возвращает их число.
+    pub fn close(&self, device: &str) -> usize {
+        let connections = self.devices.lock().unwrap().remove(device).unwrap_or_default();
+        for closed in connections.values() {
+            // Разрешение сохраняется, даже если подключение сейчас не ждёт
+            closed.notify_one();
+        }
+        connections.len()
+    }
+}
+
+/// Запись в `LiveConnections`, снимается при завершении подключения.
+pub struct LiveConnection {
+    registry: Arc<LiveConnections>,
+    device: String,
+    id: u64,
+    closed: Arc<Notify>,
+}
+
+impl LiveConnection {
+    /// Завершается, когда токен устройства отозван или перевыпущен.
+    pub async fn closed(&self) {
+        self.closed.notified().await
+    }
+}
+
+impl Drop for LiveConnection {
+    fn drop(&mut self) {
+        let mut devices = self.registry.devices.lock().unwrap();
+        if let Some(connections) = devices.get_mut(&self.device) {
+            connections.remove(&self.id);
+            if connections.is_empty() {
+                devices.remove(&self.device);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    async fn is_closed(connection: &LiveConnection) -> bool {
+        timeout(Duration::from_millis(50), connection.closed()).await.is_ok()
+    }
+
+    #[tokio::test]
+    async fn close_notifies_every_connection_of_device() {
+        let connections = Arc::new(LiveConnections::default());
+        let first = connections.register("kitchen");
+        let second = connections.register("kitchen");
+        let other = connections.register("hall");
+
+        assert_eq!(connections.close("kitchen"), 2);
+        assert!(is_closed(&first).await);
+        assert!(is_closed(&second).await);
+        assert!(!is_closed(&other).await);
+        assert_eq!(connections.close("kitchen"), 0);
+    }
+
+    #[tokio::test]
+    async fn close_before_waiting_is_not_lost() {
+        let connections = Arc::new(LiveConnections::default());
+        let connection = connections.register("kitchen");
+        connections.close("kitchen");
+        tokio::task::yield_now().await;
+        assert!(is_closed(&connection).await);
+    }
+
+    #[test]
+    fn drop_unregisters_connection() {
+        let connections = Arc::new(LiveConnections::default());
+        let first = connections.register("kitchen");
+        let second = connections.register("kitchen");
+        drop(first);
+        assert_eq!(connections.devices.lock().unwrap()["kitchen"].len(), 1);
+        drop(second);
+        assert!(connections.devices.lock().unwrap().is_empty());
+        assert_eq!(connections.close("kitchen"), 0);
+    }
+}
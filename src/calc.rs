@@ -0,0 +1,535 @@
+use anyhow::{anyhow, bail, Result};
+use std::fmt;
+
+// Дольше не считаем точно, переходим на f64
+const MAX_EXACT_POWER: u128 = 512;
+const MAX_FACTORIAL: i128 = 33;
+const MAX_FLOAT_FACTORIAL: i128 = 170;
+// Выражение приходит от модели: длину и вложенность ограничиваем, чтобы разбор не переполнил стек
+const MAX_EXPRESSION_LEN: usize = 1000;
+// Уровни рекурсии разбора: скобка стоит два уровня, функция, унарный знак и степень - по одному
+const MAX_DEPTH: usize = 128;
+
+/// Число: точная дробь, пока хватает i128, иначе f64.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Number {
+    Exact(i128, i128),
+    Approx(f64),
+}
+
+impl Number {
+    fn ratio(num: i128, den: i128) -> Result<Self> {
+        if den == 0 {
+            bail!("деление на ноль");
+        }
+        let g = gcd(num, den);
+        let (mut num, mut den) = (num / g, den / g);
+        if den < 0 {
+            match (num.checked_neg(), den.checked_neg()) {
+                (Some(n), Some(d)) => (num, den) = (n, d),
+                _ => return Ok(Number::Approx(num as f64 / den as f64)),
+            }
+        }
+        Ok(Number::Exact(num, den))
+    }
+
+    pub fn to_f64(self) -> f64 {
+        match self {
+            Number::Exact(num, den) => num as f64 / den as f64,
+            Number::Approx(value) => value,
+        }
+    }
+
+    fn integer(self) -> Option<i128> {
+        match self {
+            Number::Exact(num, 1) => Some(num),
+            _ => None,
+        }
+    }
+
+    fn add(self, other: Self) -> Result<Self> {
+        if let (Number::Exact(a, b), Number::Exact(c, d)) = (self, other) {
+            let exact = (|| Some((a.checked_mul(d)?.checked_add(c.checked_mul(b)?)?, b.checked_mul(d)?)))();
+            if let Some((num, den)) = exact {
+                return Number::ratio(num, den);
+            }
+        }
+        Ok(Number::Approx(self.to_f64() + other.to_f64()))
+    }
+
+    fn neg(self) -> Self {
+        match self {
+            Number::Exact(num, den) => match num.checked_neg() {
+                Some(num) => Number::Exact(num, den),
+                None => Number::Approx(-(num as f64) / den as f64),
+            },
+            Number::Approx(value) => Number::Approx(-value),
+        }
+    }
+
+    fn mul(self, other: Self) -> Result<Self> {
+        if let (Number::Exact(a, b), Number::Exact(c, d)) = (self, other) {
+            if let (Some(num), Some(den)) = (a.checked_mul(c), b.checked_mul(d)) {
+                return Number::ratio(num, den);
+            }
+        }
+        Ok(Number::Approx(self.to_f64() * other.to_f64()))
+    }
+
+    fn div(self, other: Self) -> Result<Self> {
+        match other {
+            Number::Exact(0, _) => bail!("деление на ноль"),
+            Number::Exact(num, den) => self.mul(Number::ratio(den, num)?),
+            Number::Approx(0.0) => bail!("деление на ноль"),
+            Number::Approx(value) => Ok(Number::Approx(self.to_f64() / value)),
+        }
+    }
+
+    fn pow(self, exponent: Self) -> Result<Self> {
+        if let (Number::Exact(..), Some(power)) = (self, exponent.integer()) {
+            if power.unsigned_abs() <= MAX_EXACT_POWER {
+                let mut result = Number::Exact(1, 1);
+                for _ in 0..power.unsigned_abs() {
+                    result = result.mul(self)?;
+                }
+                return if power < 0 { Number::Exact(1, 1).div(result) } else { Ok(result) };
+            }
+        }
+        Ok(Number::Approx(self.to_f64().powf(exponent.to_f64())))
+    }
+
+    fn factorial(self) -> Result<Self> {
+        match self.integer() {
+            Some(n) if (0..=MAX_FACTORIAL).contains(&n) => Ok(Number::Exact((1..=n).product::<i128>().max(1), 1)),
+            Some(n) if n > MAX_FACTORIAL && n <= MAX_FLOAT_FACTORIAL => {
+                Ok(Number::Approx((1..=n).fold(1.0, |acc, k| acc * k as f64)))
+            }
+            Some(n) if n > MAX_FLOAT_FACTORIAL => bail!("слишком большой факториал"),
+            _ => bail!("факториал определён только для неотрицательных целых"),
+        }
+    }
+
+    fn sqrt(self) -> Result<Self> {
+        if self.to_f64() < 0.0 {
+            bail!("корень из отрицательного числа");
+        }
+        if let Number::Exact(num, den) = self {
+            if let (Some(n), Some(d)) = (exact_sqrt(num), exact_sqrt(den)) {
+                return Number::ratio(n, d);
+            }
+        }
+        Ok(Number::Approx(self.to_f64().sqrt()))
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Number::Exact(num, 1) => write!(f, "{}", num),
+            Number::Exact(num, den) if is_finite_decimal(den) => write!(f, "{}", format_float(num as f64 / den as f64)),
+            Number::Exact(num, den) => write!(f, "{}/{} ≈ {}", num, den, format_float(num as f64 / den as f64)),
+            Number::Approx(value) => write!(f, "{}", format_float(value)),
+        }
+    }
+}
+
+/// До 12 значащих цифр, без хвостовых нулей.
+pub fn format_float(value: f64) -> String {
+    if !value.is_finite() {
+        return value.to_string();
+    }
+    if value == 0.0 {
+        return "0".to_string();
+    }
+    if value.abs() >= 1e15 || value.abs() < 1e-6 {
+        let text = format!("{:.11e}", value);
+        let (mantissa, exponent) = text.split_once('e').unwrap_or((&text, "0"));
+        let mantissa = mantissa.trim_end_matches('0').trim_end_matches('.');
+        return format!("{}e{}", mantissa, exponent);
+    }
+    let digits = (11 - value.abs().log10().floor() as i32).clamp(0, 15) as usize;
+    let text = format!("{:.*}", digits, value);
+    let text = if text.contains('.') {
+        text.trim_end_matches('0').trim_end_matches('.').to_string()
+    } else {
+        text
+    };
+    if text == "-0" {
+        "0".to_string()
+    } else {
+        text
+    }
+}
+
+/// Считает выражение: `+ - * / ^ !`, скобки, `sqrt sin cos tan asin acos atan ln log exp abs`, `pi`, `e`.
+/// Дроби и целые считаются точно.
+pub fn evaluate(expression: &str) -> Result<Number> {
+    if expression.chars().count() > MAX_EXPRESSION_LEN {
+        bail!("слишком длинное выражение");
+    }
+    let tokens = tokenize(expression)?;
+    let mut parser = Parser { tokens, pos: 0, depth: 0 };
+    let value = parser.expression()?;
+    if parser.pos != parser.tokens.len() {
+        bail!("лишние символы в выражении");
+    }
+    Ok(value)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(Number),
+    Ident(String),
+    Op(char),
+    Open,
+    Close,
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            ' ' | '\t' => i += 1,
+            '0'..='9' | '.' | ',' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.' || chars[i] == ',') {
+                    i += 1;
+                }
+                let literal: String = chars[start..i].iter().map(|&c| if c == ',' { '.' } else { c }).collect();
+                let mut value = parse_decimal(&literal)?;
+
+                // 1e5, 6.02e-23
+                let sign = matches!(chars.get(i + 1), Some('+' | '-')) as usize;
+                if matches!(chars.get(i), Some('e' | 'E')) && chars.get(i + 1 + sign).is_some_and(|c| c.is_ascii_digit()) {
+                    let exp_start = i + 1;
+                    i = exp_start + sign;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                    let exponent: String = chars[exp_start..i].iter().collect();
+                    let exponent = exponent.parse::<i128>().map_err(|_| anyhow!("неверная степень '{}'", exponent))?;
+                    value = value.mul(Number::Exact(10, 1).pow(Number::Exact(exponent, 1))?)?;
+                }
+                tokens.push(Token::Number(value));
+            }
+            '+' | '-' | '/' | '^' | '!' => {
+                tokens.push(Token::Op(c));
+                i += 1;
+            }
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                tokens.push(Token::Op('^'));
+                i += 2;
+            }
+            '*' | '×' | '·' => {
+                tokens.push(Token::Op('*'));
+                i += 1;
+            }
+            ':' | '÷' => {
+                tokens.push(Token::Op('/'));
+                i += 1;
+            }
+            '−' => {
+                tokens.push(Token::Op('-'));
+                i += 1;
+            }
+            '(' | '[' => {
+                tokens.push(Token::Open);
+                i += 1;
+            }
+            ')' | ']' => {
+                tokens.push(Token::Close);
+                i += 1;
+            }
+            '√' => {
+                tokens.push(Token::Ident("sqrt".to_string()));
+                i += 1;
+            }
+            'π' => {
+                tokens.push(Token::Ident("pi".to_string()));
+                i += 1;
+            }
+            c if c.is_alphabetic() => {
+                let start = i;
+                while i < chars.len() && chars[i].is_alphanumeric() {
+                    i += 1;
+                }
+                tokens.push(Token::Ident(chars[start..i].iter().collect::<String>().to_lowercase()));
+            }
+            _ => bail!("непонятный символ '{}'", c),
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn parse_decimal(literal: &str) -> Result<Number> {
+    let (whole, fraction) = literal.split_once('.').unwrap_or((literal, ""));
+    if fraction.contains('.') || (whole.is_empty() && fraction.is_empty()) {
+        bail!("неверное число '{}'", literal);
+    }
+    let digits = format!("{}{}", whole, fraction);
+    match (digits.parse::<i128>(), 10i128.checked_pow(fraction.len() as u32)) {
+        (Ok(num), Some(den)) => Number::ratio(num, den),
+        _ => literal
+            .parse::<f64>()
+            .map(Number::Approx)
+            .map_err(|_| anyhow!("неверное число '{}'", literal)),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    /// Спуск на уровень глубже; после ошибки разбор не продолжается, поэтому счётчик не восстанавливаем.
+    fn descend(&mut self) -> Result<()> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            bail!("слишком глубокая вложенность");
+        }
+        Ok(())
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    // expression := term (('+' | '-') term)*
+    fn expression(&mut self) -> Result<Number> {
+        let mut value = self.term()?;
+        while let Some(Token::Op(op @ ('+' | '-'))) = self.peek().cloned() {
+            self.pos += 1;
+            let rhs = self.term()?;
+            value = if op == '+' { value.add(rhs)? } else { value.add(rhs.neg())? };
+        }
+        Ok(value)
+    }
+
+    // term := unary (('*' | '/' | неявное умножение) unary)*
+    fn term(&mut self) -> Result<Number> {
+        let mut value = self.unary()?;
+        loop {
+            match self.peek() {
+                Some(Token::Op('*')) => {
+                    self.pos += 1;
+                    value = value.mul(self.unary()?)?;
+                }
+                Some(Token::Op('/')) => {
+                    self.pos += 1;
+                    value = value.div(self.unary()?)?;
+                }
+                // 2pi, 3(4+5)
+                Some(Token::Open) | Some(Token::Ident(_)) | Some(Token::Number(_)) => {
+                    value = value.mul(self.unary()?)?;
+                }
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    // unary := ('-' | '+') unary | power
+    fn unary(&mut self) -> Result<Number> {
+        self.descend()?;
+        let value = match self.peek() {
+            Some(Token::Op('-')) => {
+                self.pos += 1;
+                self.unary()?.neg()
+            }
+            Some(Token::Op('+')) => {
+                self.pos += 1;
+                self.unary()?
+            }
+            _ => self.power()?,
+        };
+        self.depth -= 1;
+        Ok(value)
+    }
+
+    // power := postfix ('^' unary)?  - правоассоциативно, -2^2 = -4
+    fn power(&mut self) -> Result<Number> {
+        let base = self.postfix()?;
+        if let Some(Token::Op('^')) = self.peek() {
+            self.pos += 1;
+            let exponent = self.unary()?;
+            return base.pow(exponent);
+        }
+        Ok(base)
+    }
+
+    fn postfix(&mut self) -> Result<Number> {
+        let mut value = self.primary()?;
+        while let Some(Token::Op('!')) = self.peek() {
+            self.pos += 1;
+            value = value.factorial()?;
+        }
+        Ok(value)
+    }
+
+    fn primary(&mut self) -> Result<Number> {
+        self.descend()?;
+        let value = self.operand();
+        self.depth -= 1;
+        value
+    }
+
+    fn operand(&mut self) -> Result<Number> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(value),
+            Some(Token::Open) => {
+                let value = self.expression()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(value),
+                    _ => bail!("не хватает закрывающей скобки"),
+                }
+            }
+            Some(Token::Ident(name)) => match name.as_str() {
+                "pi" | "пи" => Ok(Number::Approx(std::f64::consts::PI)),
+                "e" => Ok(Number::Approx(std::f64::consts::E)),
+                _ => {
+                    // sin(x)^2 - квадрат синуса, sin x^2 - синус квадрата
+                    let argument = if self.peek() == Some(&Token::Open) {
+                        self.primary()?
+                    } else {
+                        self.power()?
+                    };
+                    apply_function(&name, argument)
+                }
+            },
+            Some(token) => bail!("неожиданный символ {:?}", token),
+            None => bail!("выражение оборвано"),
+        }
+    }
+}
+
+fn apply_function(name: &str, argument: Number) -> Result<Number> {
+    let x = argument.to_f64();
+    let value = match name {
+        "sqrt" | "корень" => return argument.sqrt(),
+        "abs" => {
+            return Ok(if x < 0.0 { argument.neg() } else { argument });
+        }
+        "sin" => x.sin(),
+        "cos" => x.cos(),
+        "tan" | "tg" => x.tan(),
+        "asin" | "arcsin" => x.asin(),
+        "acos" | "arccos" => x.acos(),
+        "atan" | "arctan" | "arctg" => x.atan(),
+        "ln" => x.ln(),
+        "log" | "lg" => x.log10(),
+        "exp" => x.exp(),
+        _ => bail!("неизвестная функция '{}'", name),
+    };
+    if value.is_nan() {
+        bail!("{} не определён для {}", name, format_float(x));
+    }
+    Ok(Number::Approx(value))
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+    let (mut a, mut b) = (a.unsigned_abs(), b.unsigned_abs());
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    (a.max(1)).min(i128::MAX as u128) as i128
+}
+
+fn exact_sqrt(value: i128) -> Option<i128> {
+    if value < 0 {
+        return None;
+    }
+    let root = (value as f64).sqrt().round() as i128;
+    (root.checked_mul(root) == Some(value)).then_some(root)
+}
+
+fn is_finite_decimal(mut den: i128) -> bool {
+    for factor in [2, 5] {
+        while den % factor == 0 {
+            den /= factor;
+        }
+    }
+    den == 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expression: &str) -> Number {
+        evaluate(expression).unwrap()
+    }
+
+    #[test]
+    fn fractions_stay_exact() {
+        assert_eq!(eval("1/3 + 1/6"), Number::Exact(1, 2));
+        assert_eq!(eval("0.1 + 0.2"), Number::Exact(3, 10));
+        assert_eq!(eval("2^-2"), Number::Exact(1, 4));
+        assert_eq!(eval("sqrt(9/4)"), Number::Exact(3, 2));
+        assert_eq!(eval("1/3").to_string(), "1/3 ≈ 0.333333333333");
+        assert_eq!(eval("3/4").to_string(), "0.75");
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        assert_eq!(eval("2 + 3 * 4"), Number::Exact(14, 1));
+        assert_eq!(eval("(2 + 3) * 4"), Number::Exact(20, 1));
+        assert_eq!(eval("10 - 4 - 3"), Number::Exact(3, 1));
+        assert_eq!(eval("2 ^ 3 ^ 2"), Number::Exact(512, 1));
+        assert_eq!(eval("-2 ^ 2"), Number::Exact(-4, 1));
+        assert_eq!(eval("3! * 2"), Number::Exact(12, 1));
+        assert_eq!(eval("3(4 + 5)"), Number::Exact(27, 1));
+    }
+
+    #[test]
+    fn factorial_limits() {
+        assert_eq!(eval("5!"), Number::Exact(120, 1));
+        assert_eq!(eval("0!"), Number::Exact(1, 1));
+        assert!(matches!(eval("33!"), Number::Exact(..)));
+        assert!(matches!(eval("34!"), Number::Approx(_)));
+        assert!(evaluate("171!").is_err());
+        assert!(evaluate("(-1)!").is_err());
+        assert!(evaluate("2.5!").is_err());
+    }
+
+    #[test]
+    fn overflow_falls_back_to_float() {
+        assert!(matches!(eval("2^127"), Number::Approx(_)));
+        assert!(matches!(eval("10^600"), Number::Approx(_)));
+        assert_eq!(eval("170141183460469231731687303715884105727 + 1").to_f64(), 2f64.powi(127));
+        // Показатель i128::MIN
+        assert_eq!(eval("2^((-2)^127)").to_f64(), 0.0);
+        assert_eq!(eval("1e-170141183460469231731687303715884105728").to_f64(), 0.0);
+    }
+
+    #[test]
+    fn deep_nesting_is_rejected() {
+        let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(eval(&nested(30)), Number::Exact(1, 1));
+        assert!(evaluate(&nested(490)).is_err());
+        assert!(evaluate(&format!("{}1", "-".repeat(900))).is_err());
+        assert!(evaluate(&format!("{}2", "2^".repeat(400))).is_err());
+        assert!(evaluate(&format!("{}1", "sin ".repeat(200))).is_err());
+        assert!(evaluate(&"1+".repeat(600)).is_err());
+    }
+
+    #[test]
+    fn errors() {
+        assert!(evaluate("1/0").is_err());
+        assert!(evaluate("sqrt(-1)").is_err());
+        assert!(evaluate("2 +").is_err());
+        assert!(evaluate("(2 + 3").is_err());
+        assert!(evaluate("foo(1)").is_err());
+    }
+}
@@ -0,0 +1,258 @@
+use std::{collections::HashMap, collections::VecDeque, env};
+use tracing::info;
+
+use crate::provider::ChatMessage;
+
+// Сколько обменов держим в памяти сессии
+const HISTORY_CAPACITY: usize = 50;
+const DEFAULT_BUDGET: usize = 3000;
+// Служебные токены роли и разметки на каждое сообщение
+const MESSAGE_OVERHEAD: usize = 4;
+// Запас под следующий вопрос, когда решаем, что уже не влезет
+const QUESTION_RESERVE: usize = 256;
+
+/// История разговора: кольцевой буфер пар (вопрос, ответ), старые вытесняются.
+/// То, что выпало из окна контекста, хранится пересказом в `summary`.
+#[derive(Clone)]
+pub struct ConversationHistory {
+    exchanges: VecDeque<(String, String)>,
+    summary: String,
+}
+
+impl Default for ConversationHistory {
+    fn default() -> Self {
+        Self {
+            exchanges: VecDeque::with_capacity(HISTORY_CAPACITY),
+            summary: String::new(),
+        }
+    }
+}
+
+impl ConversationHistory {
+    pub fn push(&mut self, question: String, answer: String) {
+        if self.exchanges.len() == HISTORY_CAPACITY {
+            self.exchanges.pop_front();
+        }
+        self.exchanges.push_back((question, answer));
+    }
+
+    pub fn clear(&mut self) {
+        self.exchanges.clear();
+        self.summary.clear();
+    }
+
+    pub fn summary(&self) -> &str {
+        &self.summary
+    }
+
+    /// Старые обмены, которые со следующим вопросом уже не влезут в бюджет.
+    /// История не меняется, пока пересказ не готов.
+    pub fn overflow(&self, system_prompt: &str, budget: usize) -> Vec<(String, String)> {
+        let mut used = estimate_tokens(system_prompt) + estimate_tokens(&self.summary) + QUESTION_RESERVE;
+        let mut keep = 0;
+        for (question, answer) in self.newest_first() {
+            let cost = estimate_tokens(question) + estimate_tokens(answer);
+            if used + cost > budget {
+                break;
+            }
+            used += cost;
+            keep += 1;
+        }
+
+        let dropped = self.exchanges.len() - keep;
+        self.exchanges.iter().take(dropped).cloned().collect()
+    }
+
+    /// Заменяет пересказанные обмены конспектом. Если история за время пересказа
+    /// изменилась (например, её очистили), ничего не трогает.
+    pub fn apply_summary(&mut self, dropped: &[(String, String)], summary: String) -> bool {
+        if !self.exchanges.iter().take(dropped.len()).eq(dropped.iter()) {
+            return false;
+        }
+        self.exchanges.drain(..dropped.len());
+        self.summary = summary;
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.exchanges.len()
+    }
+
+    /// От новых к старым.
+    pub fn newest_first(&self) -> impl Iterator<Item = &(String, String)> {
+        self.exchanges.iter().rev()
+    }
+}
+
+/// Бюджет токенов на промпт: `CONTEXT_BUDGET` по умолчанию и
+/// `CONTEXT_BUDGETS=модель=токены,...` для отдельных моделей.
+pub struct ContextBudget {
+    default: usize,
+    per_model: HashMap<String, usize>,
+}
+
+impl ContextBudget {
+    pub fn from_env() -> Self {
+        let default = env::var("CONTEXT_BUDGET")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_BUDGET);
+
+        let per_model: HashMap<String, usize> = env::var("CONTEXT_BUDGETS")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|entry| {
+                let (model, tokens) = entry.rsplit_once('=')?;
+                Some((model.trim().to_string(), tokens.trim().parse().ok()?))
+            })
+            .collect();
+
+        info!("Бюджет контекста: {} токенов, для моделей: {:?}", default, per_model);
+        Self { default, per_model }
+    }
+
+    pub fn for_model(&self, model: &str) -> usize {
+        self.per_model.get(model).copied().unwrap_or(self.default)
+    }
+}
+
+/// Грубая оценка без токенизатора: латиница ~4 символа на токен, кириллица ~2.
+pub fn estimate_tokens(text: &str) -> usize {
+    let (ascii, other): (usize, usize) = text
+        .chars()
+        .fold((0, 0), |(ascii, other), c| if c.is_ascii() { (ascii + 1, other) } else { (ascii, other + 1) });
+    ascii.div_ceil(4) + other.div_ceil(2) + MESSAGE_OVERHEAD
+}
+
+/// Системный промпт с пересказом начала разговора, история от новых реплик
+/// к старым, пока влезает в бюджет, и новый вопрос.
+pub fn context_messages(
+    text: &str,
+    history: &ConversationHistory,
+    system_prompt: &str,
+    budget: usize,
+) -> Vec<ChatMessage> {
+    let system_prompt = if history.summary.is_empty() {
+        system_prompt.to_string()
+    } else {
+        format!("{}\n\nРанее в разговоре: {}", system_prompt, history.summary)
+    };
+
+    let mut used = estimate_tokens(&system_prompt) + estimate_tokens(text);
+    let mut recent = Vec::new();
+    for (question, answer) in history.newest_first() {
+        let cost = estimate_tokens(question) + estimate_tokens(answer);
+        if used + cost > budget {
+            break;
+        }
+        used += cost;
+        recent.push((question, answer));
+    }
+
+    let mut messages = vec![ChatMessage::new("system", system_prompt)];
+    for (question, answer) in recent.into_iter().rev() {
+        messages.push(ChatMessage::new("user", question.as_str()));
+        messages.push(ChatMessage::new("assistant", answer.as_str()));
+    }
+    messages.push(ChatMessage::new("user", text));
+
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 40 латинских символов: 10 токенов текста и 4 служебных
+    const EXCHANGE_TOKENS: usize = 2 * (10 + MESSAGE_OVERHEAD);
+
+    fn text(tag: &str, index: usize) -> String {
+        format!("{}{:0>39}", tag, index)
+    }
+
+    fn history(exchanges: usize) -> ConversationHistory {
+        let mut history = ConversationHistory::default();
+        for i in 0..exchanges {
+            history.push(text("q", i), text("a", i));
+        }
+        history
+    }
+
+    #[test]
+    fn estimates_latin_and_cyrillic() {
+        assert_eq!(estimate_tokens(""), MESSAGE_OVERHEAD);
+        assert_eq!(estimate_tokens("abcd"), 1 + MESSAGE_OVERHEAD);
+        assert_eq!(estimate_tokens("abcde"), 2 + MESSAGE_OVERHEAD);
+        assert_eq!(estimate_tokens("абвг"), 2 + MESSAGE_OVERHEAD);
+        assert_eq!(estimate_tokens(&text("q", 1)), EXCHANGE_TOKENS / 2);
+    }
+
+    #[test]
+    fn ring_buffer_drops_oldest() {
+        let history = history(HISTORY_CAPACITY + 5);
+        assert_eq!(history.len(), HISTORY_CAPACITY);
+        assert_eq!(history.newest_first().last().unwrap().0, text("q", 5));
+    }
+
+    #[test]
+    fn context_keeps_newest_exchanges_within_budget() {
+        let history = history(10);
+        // Пустой системный промпт и вопрос "q": 4 + 5 токенов
+        let budget = 9 + 3 * EXCHANGE_TOKENS;
+        let messages = context_messages("q", &history, "", budget);
+
+        let contents: Vec<&str> = messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(messages.len(), 1 + 3 * 2 + 1);
+        assert_eq!(contents[1], text("q", 7));
+        assert_eq!(contents[6], text("a", 9));
+        assert_eq!(contents[7], "q");
+
+        let messages = context_messages("q", &history, "", budget - 1);
+        assert_eq!(messages.len(), 1 + 2 * 2 + 1);
+    }
+
+    #[test]
+    fn summary_goes_into_system_prompt() {
+        let mut history = history(0);
+        history.summary = "ученик решал уравнения".to_string();
+        let messages = context_messages("q", &history, "Промпт", 1000);
+        assert_eq!(messages[0].content, "Промпт\n\nРанее в разговоре: ученик решал уравнения");
+    }
+
+    #[test]
+    fn overflow_does_not_change_history() {
+        let history = history(10);
+        // Пустые промпт и конспект и запас под вопрос
+        let budget = 2 * MESSAGE_OVERHEAD + QUESTION_RESERVE + 2 * EXCHANGE_TOKENS;
+        let dropped = history.overflow("", budget);
+
+        assert_eq!(dropped.len(), 8);
+        assert_eq!(dropped[0], (text("q", 0), text("a", 0)));
+        assert_eq!(history.len(), 10);
+        assert!(history.overflow("", 10_000).is_empty());
+    }
+
+    #[test]
+    fn apply_summary_replaces_dropped_exchanges() {
+        let mut history = history(10);
+        let budget = 2 * MESSAGE_OVERHEAD + QUESTION_RESERVE + 2 * EXCHANGE_TOKENS;
+        let dropped = history.overflow("", budget);
+
+        assert!(history.apply_summary(&dropped, "конспект".to_string()));
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.summary(), "конспект");
+        assert_eq!(history.newest_first().last().unwrap().0, text("q", 8));
+    }
+
+    #[test]
+    fn apply_summary_skips_changed_history() {
+        let mut history = history(10);
+        let dropped = history.overflow("", 0);
+        history.clear();
+        history.push("новый".to_string(), "разговор".to_string());
+
+        assert!(!history.apply_summary(&dropped, "конспект".to_string()));
+        assert_eq!(history.len(), 1);
+        assert_eq!(history.summary(), "");
+    }
+}
@@ -0,0 +1,381 @@
+use serde::{Deserialize, Serialize};
+
+use crate::glyphs;
+use crate::protocol::ServerMessage;
+use crate::render;
+
+// Больше не бывает у экранов устройств; ограничивает память под страницу-картинку
+const MAX_SCREEN_SIZE: u32 = 1024;
+const MAX_GLYPH_SIZE: u32 = 64;
+
+/// Какие символы есть в шрифте устройства.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FontCoverage {
+    Ascii,
+    Cyrillic,
+    Unicode,
+    /// Встроенный шрифт сервера, которым рисуются страницы-картинки
+    #[serde(skip)]
+    Bundled,
+}
+
+/// Как отдавать страницы ответа: готовыми строками или картинками.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PageFormat {
+    #[default]
+    Text,
+    Bitmap,
+}
+
+impl PageFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "on" | "text" => Some(PageFormat::Text),
+            "bitmap" => Some(PageFormat::Bitmap),
+            _ => None,
+        }
+    }
+}
+
+/// Параметры экрана, которые устройство сообщает при подключении.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisplayProfile {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub glyph_width: u32,
+    pub line_height: u32,
+    pub max_lines: u32,
+    /// Строк текста на странице; по умолчанию сколько влезает по высоте
+    pub page_lines: Option<u32>,
+    pub font: FontCoverage,
+    /// Символы сверх набора `font`, которые тоже есть в шрифте устройства
+    pub extra_glyphs: String,
+}
+
+impl Default for DisplayProfile {
+    fn default() -> Self {
+        Self::ssd1306()
+    }
+}
+
+impl DisplayProfile {
+    /// OLED 128x64 с u8g2_font_6x13_t_cyrillic
+    pub fn ssd1306() -> Self {
+        Self {
+            name: "ssd1306".to_string(),
+            width: 128,
+            height: 64,
+            glyph_width: 6,
+            line_height: 13,
+            max_lines: 90,
+            // Нижняя строка занята подсказкой и номером страницы
+            page_lines: Some(3),
+            font: FontCoverage::Cyrillic,
+            extra_glyphs: String::new(),
+        }
+    }
+
+    /// TFT 160x128 с тем же шрифтом 6x13
+    pub fn st7735() -> Self {
+        Self {
+            name: "st7735".to_string(),
+            width: 160,
+            height: 128,
+            glyph_width: 6,
+            line_height: 13,
+            max_lines: 40,
+            page_lines: Some(8),
+            font: FontCoverage::Cyrillic,
+            extra_glyphs: String::new(),
+        }
+    }
+
+    /// `profile:ssd1306`, `profile:st7735` или `profile:{json}`.
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if value.starts_with('{') {
+            return serde_json::from_str::<Self>(value).ok().filter(|p| p.is_valid());
+        }
+        match value.to_lowercase().as_str() {
+            "ssd1306" | "oled" | "128x64" => Some(Self::ssd1306()),
+            "st7735" | "tft" | "160x128" => Some(Self::st7735()),
+            _ => None,
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        (1..=MAX_SCREEN_SIZE).contains(&self.width)
+            && (1..=MAX_SCREEN_SIZE).contains(&self.height)
+            && (1..=MAX_GLYPH_SIZE).contains(&self.glyph_width)
+            && (1..=MAX_GLYPH_SIZE).contains(&self.line_height)
+            && self.max_lines > 0
+            && self.width >= self.glyph_width
+            && self.height >= self.line_height
+            && self.page_lines != Some(0)
+    }
+
+    pub fn chars_per_line(&self) -> usize {
+        (self.width / self.glyph_width) as usize
+    }
+
+    pub fn visible_lines(&self) -> usize {
+        (self.height / self.line_height) as usize
+    }
+
+    /// Строк на странице; больше, чем влезает на экран, не бывает.
+    pub fn lines_per_page(&self) -> usize {
+        let visible = self.visible_lines();
+        self.page_lines.map_or(visible, |lines| (lines as usize).min(visible))
+    }
+
+    pub fn max_answer_chars(&self) -> usize {
+        self.chars_per_line() * self.max_lines as usize
+    }
+
+    /// Подставляет параметры экрана в `{width}`, `{height}`, `{chars_per_line}`, `{max_lines}`.
+    pub fn fill_prompt(&self, prompt: &str) -> String {
+        prompt
+            .replace("{width}", &self.width.to_string())
+            .replace("{height}", &self.height.to_string())
+            .replace("{chars_per_line}", &self.chars_per_line().to_string())
+            .replace("{visible_lines}", &self.visible_lines().to_string())
+            .replace("{max_lines}", &self.max_lines.to_string())
+    }
+
+    /// Обрезает ответ до того, что экран сможет показать, по границе предложения или слова.
+    pub fn limit_answer(&self, answer: &str) -> String {
+        let limit = self.max_answer_chars();
+        if answer.chars().count() <= limit {
+            return answer.to_string();
+        }
+
+        let cut: String = answer.chars().take(limit.saturating_sub(1)).collect();
+        let boundary = cut
+            .rfind(['.', '!', '?'])
+            .map(|pos| pos + 1)
+            .filter(|&pos| pos * 2 >= cut.len())
+            .or_else(|| cut.rfind(char::is_whitespace));
+
+        match boundary {
+            Some(pos) => format!("{}…", cut[..pos].trim_end()),
+            None => format!("{}…", cut),
+        }
+    }
+
+    /// Профиль для страниц-картинок: встроенный шрифт, весь экран под текст.
+    fn for_bitmap(&self) -> Self {
+        Self {
+            page_lines: None,
+            font: FontCoverage::Bundled,
+            extra_glyphs: String::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Заменяет символы, которых нет в шрифте устройства.
+    pub fn fit_font(&self, text: &str) -> String {
+        glyphs::fit_to_font(text, self.font, &self.extra_glyphs)
+    }
+
+    /// Переносит текст по словам под ширину экрана; слишком длинные слова режет.
+    pub fn wrap(&self, text: &str) -> Vec<String> {
+        let width = self.chars_per_line().max(1);
+        let mut lines = Vec::new();
+
+        for paragraph in self.fit_font(text).lines() {
+            let mut line = String::new();
+            let mut line_len = 0;
+
+            for word in paragraph.split_whitespace() {
+                let word_len = word.chars().count();
+                if line_len > 0 && line_len + 1 + word_len <= width {
+                    line.push(' ');
+                    line.push_str(word);
+                    line_len += 1 + word_len;
+                    continue;
+                }
+                if line_len > 0 {
+                    lines.push(std::mem::take(&mut line));
+                }
+
+                let chars: Vec<char> = word.chars().collect();
+                let mut chunks = chars.chunks(width).peekable();
+                while let Some(chunk) = chunks.next() {
+                    if chunks.peek().is_some() {
+                        lines.push(chunk.iter().collect());
+                    } else {
+                        line = chunk.iter().collect();
+                        line_len = chunk.len();
+                    }
+                }
+            }
+
+            if line_len > 0 {
+                lines.push(line);
+            }
+        }
+
+        lines
+    }
+
+    /// Разбивает текст на страницы по `lines_per_page` строк.
+    pub fn paginate(&self, text: &str) -> Vec<Vec<String>> {
+        self.wrap(text)
+            .chunks(self.lines_per_page().max(1))
+            .map(|page| page.to_vec())
+            .collect()
+    }
+}
+
+#[derive(Serialize)]
+pub struct Page {
+    index: usize,
+    total: usize,
+    lines: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct BitmapPage {
+    index: usize,
+    total: usize,
+    width: u32,
+    height: u32,
+    format: &'static str,
+    bytes: usize,
+}
+
+/// Последний ответ, разбитый на страницы, и текущая страница.
+#[derive(Clone, Default)]
+pub struct Pager {
+    pages: Vec<Vec<String>>,
+    current: usize,
+    profile: DisplayProfile,
+    format: PageFormat,
+}
+
+impl Pager {
+    pub fn new(profile: &DisplayProfile, text: &str, format: PageFormat) -> Self {
+        let profile = match format {
+            PageFormat::Text => profile.clone(),
+            PageFormat::Bitmap => profile.for_bitmap(),
+        };
+        Self {
+            pages: profile.paginate(text),
+            current: 0,
+            profile,
+            format,
+        }
+    }
+
+    /// Следующая страница, после последней - снова первая.
+    pub fn next_page(&mut self) {
+        if !self.pages.is_empty() {
+            self.current = (self.current + 1) % self.pages.len();
+        }
+    }
+
+    fn lines(&self) -> &[String] {
+        self.pages.get(self.current).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Текущая страница строками или заголовок картинки.
+    pub fn message(&self) -> ServerMessage {
+        match self.format {
+            PageFormat::Text => ServerMessage::Page(Page {
+                index: self.current,
+                total: self.pages.len(),
+                lines: self.lines().to_vec(),
+            }),
+            PageFormat::Bitmap => ServerMessage::Bitmap(BitmapPage {
+                index: self.current,
+                total: self.pages.len(),
+                width: self.profile.width,
+                height: self.profile.height,
+                format: "xbm",
+                bytes: self.profile.width.div_ceil(8) as usize * self.profile.height as usize,
+            }),
+        }
+    }
+
+    /// Картинка текущей страницы, если страницы отдаются картинками.
+    pub fn bitmap(&self) -> Option<Vec<u8>> {
+        (self.format == PageFormat::Bitmap).then(|| {
+            render::render_page(&self.profile, self.lines(), self.current, self.pages.len()).into_bytes()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Экран на 10 символов и 4 строки.
+    fn small(page_lines: Option<u32>, max_lines: u32) -> DisplayProfile {
+        DisplayProfile {
+            name: "test".to_string(),
+            width: 60,
+            height: 52,
+            glyph_width: 6,
+            line_height: 13,
+            max_lines,
+            page_lines,
+            font: FontCoverage::Unicode,
+            extra_glyphs: String::new(),
+        }
+    }
+
+    #[test]
+    fn wrap_breaks_on_words() {
+        let profile = small(None, 10);
+        assert_eq!(profile.wrap("один два три четыре"), ["один два", "три четыре"]);
+        assert_eq!(profile.wrap("ровно10сим и"), ["ровно10сим", "и"]);
+    }
+
+    #[test]
+    fn wrap_cuts_long_words() {
+        let profile = small(None, 10);
+        assert_eq!(
+            profile.wrap("до электрокардиограммы после"),
+            ["до", "электрокар", "диограммы", "после"]
+        );
+        assert_eq!(profile.wrap("a bbbbbbbbbbbbbbbbbbbbbbb c"), ["a", "bbbbbbbbbb", "bbbbbbbbbb", "bbb c"]);
+    }
+
+    #[test]
+    fn wrap_skips_empty_paragraphs() {
+        let profile = small(None, 10);
+        assert_eq!(profile.wrap("раз\n\n  \nдва"), ["раз", "два"]);
+        assert!(profile.wrap("").is_empty());
+    }
+
+    #[test]
+    fn paginate_fits_pages_to_screen() {
+        let text = "1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25";
+        let pages = small(Some(2), 10).paginate(text);
+        assert_eq!(pages.iter().map(Vec::len).collect::<Vec<_>>(), [2, 2, 2, 1]);
+        assert_eq!(pages[0], ["1 2 3 4 5", "6 7 8 9 10"]);
+
+        // Больше строк, чем видно на экране, на страницу не попадает
+        let pages = small(Some(10), 10).paginate(text);
+        assert_eq!(pages.iter().map(Vec::len).collect::<Vec<_>>(), [4, 3]);
+        assert_eq!(small(None, 10).lines_per_page(), 4);
+        assert!(small(None, 10).paginate("").is_empty());
+    }
+
+    #[test]
+    fn limit_answer_cuts_at_sentence_or_word() {
+        let profile = small(None, 2);
+        assert_eq!(profile.max_answer_chars(), 20);
+        assert_eq!(profile.limit_answer("Короткий ответ."), "Короткий ответ.");
+        assert_eq!(profile.limit_answer("First one. Second two three"), "First one.…");
+        assert_eq!(profile.limit_answer("One two. Three four five six"), "One two. Three…");
+        let word = "ж".repeat(30);
+        let limited = profile.limit_answer(&word);
+        assert_eq!(limited.chars().count(), 20);
+        assert!(limited.ends_with("ж…"));
+    }
+}
@@ -0,0 +1,70 @@
+use std::fmt;
+
+/// Ошибки, о которых сообщаем клиенту. Код стабилен, текст - для маленького экрана.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssistantError {
+    /// Модель не ответила или ответила ошибкой
+    Upstream(String),
+    /// Не удалось распознать речь
+    Transcription(String),
+    /// Лимит запросов: наш или апстрима
+    RateLimited { retry_after_secs: Option<u64> },
+    /// Код Морзе не расшифровывается
+    Decode(String),
+    /// Аудио пустое или не читается
+    AudioFormat(String),
+    /// Непонятное сообщение от клиента
+    Protocol(String),
+}
+
+impl AssistantError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AssistantError::Upstream(_) => "upstream",
+            AssistantError::Transcription(_) => "transcription",
+            AssistantError::RateLimited { .. } => "rate_limited",
+            AssistantError::Decode(_) => "decode",
+            AssistantError::AudioFormat(_) => "audio_format",
+            AssistantError::Protocol(_) => "protocol",
+        }
+    }
+
+    pub fn user_message(&self) -> String {
+        match self {
+            AssistantError::Upstream(_) => "Сервис ИИ недоступен, попробуйте позже".to_string(),
+            AssistantError::Transcription(_) => "Не удалось распознать речь".to_string(),
+            AssistantError::RateLimited {
+                retry_after_secs: Some(secs),
+            } => format!("Подождите {} с", secs),
+            AssistantError::RateLimited { retry_after_secs: None } => "Слишком много запросов, подождите".to_string(),
+            AssistantError::Decode(_) => "Не удалось расшифровать Морзе".to_string(),
+            AssistantError::AudioFormat(_) => "Нет аудио данных".to_string(),
+            AssistantError::Protocol(_) => "Непонятная команда".to_string(),
+        }
+    }
+
+    /// Достаёт типизированную ошибку из цепочки `anyhow`, иначе оборачивает через `wrap`.
+    pub fn classify(error: anyhow::Error, wrap: fn(String) -> AssistantError) -> Self {
+        match error.downcast::<AssistantError>() {
+            Ok(error) => error,
+            Err(error) => wrap(format!("{:#}", error)),
+        }
+    }
+}
+
+impl fmt::Display for AssistantError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssistantError::Upstream(detail)
+            | AssistantError::Transcription(detail)
+            | AssistantError::Decode(detail)
+            | AssistantError::AudioFormat(detail)
+            | AssistantError::Protocol(detail) => write!(f, "{}: {}", self.code(), detail),
+            AssistantError::RateLimited { retry_after_secs } => {
+                write!(f, "{} (retry after {:?} s)", self.code(), retry_after_secs)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AssistantError {}
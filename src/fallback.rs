@@ -0,0 +1,256 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures_util::{stream, StreamExt};
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::provider::{ChatMessage, ChatOptions, ChatProvider, DeltaStream};
+
+// Пометка в начале ответа, если ответила не основная модель
+pub const FALLBACK_NOTE: &str = "[резерв] ";
+
+pub struct FallbackStep {
+    pub provider: Arc<dyn ChatProvider>,
+    /// `None` - модель из настроек сессии
+    pub model: Option<String>,
+}
+
+impl FallbackStep {
+    /// Пока есть следующий шаг, лимит 429 не пережидаем, а сразу переходим к нему.
+    fn options(&self, options: &ChatOptions, has_next: bool) -> ChatOptions {
+        let mut options = options.clone();
+        if let Some(model) = &self.model {
+            options.model = model.clone();
+        }
+        options.fail_fast_on_rate_limit |= has_next;
+        options
+    }
+}
+
+/// Цепочка моделей: при ошибке, лимите или пустом ответе пробует следующую по порядку.
+pub struct FallbackChain {
+    name: String,
+    steps: Vec<FallbackStep>,
+}
+
+impl FallbackChain {
+    pub fn new(steps: Vec<FallbackStep>) -> Self {
+        let name = steps
+            .iter()
+            .map(|step| match &step.model {
+                Some(model) => format!("{} [{}]", step.provider.name(), model),
+                None => step.provider.name().to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(" -> ");
+
+        Self { name, steps }
+    }
+}
+
+#[async_trait]
+impl ChatProvider for FallbackChain {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn chat(&self, messages: Vec<ChatMessage>, options: &ChatOptions) -> Result<String> {
+        let mut last_error = anyhow!("No chat providers configured");
+
+        for (index, step) in self.steps.iter().enumerate() {
+            let options = step.options(options, index + 1 < self.steps.len());
+            match step.provider.chat(messages.clone(), &options).await {
+                Ok(answer) if !answer.trim().is_empty() => {
+                    return Ok(if index == 0 {
+                        answer
+                    } else {
+                        format!("{}{}", FALLBACK_NOTE, answer)
+                    });
+                }
+                Ok(_) => {
+                    warn!("{} ({}) вернул пустой ответ", step.provider.name(), options.model);
+                    last_error = anyhow!("No response from {}", step.provider.name());
+                }
+                Err(e) => {
+                    warn!("{} ({}) не ответил: {}", step.provider.name(), options.model, e);
+                    last_error = e;
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Переключиться можно только до первого куска ответа: дальше клиент его уже видел.
+    async fn chat_stream(&self, messages: Vec<ChatMessage>, options: &ChatOptions) -> Result<DeltaStream> {
+        let mut last_error = anyhow!("No chat providers configured");
+
+        for (index, step) in self.steps.iter().enumerate() {
+            let options = step.options(options, index + 1 < self.steps.len());
+            let mut deltas = match step.provider.chat_stream(messages.clone(), &options).await {
+                Ok(deltas) => deltas,
+                Err(e) => {
+                    warn!("{} ({}) не ответил: {}", step.provider.name(), options.model, e);
+                    last_error = e;
+                    continue;
+                }
+            };
+
+            match deltas.next().await {
+                Some(Ok(first)) => {
+                    let mut head = Vec::new();
+                    if index > 0 {
+                        head.push(Ok(FALLBACK_NOTE.to_string()));
+                    }
+                    head.push(Ok(first));
+                    return Ok(Box::pin(stream::iter(head).chain(deltas)));
+                }
+                Some(Err(e)) => {
+                    warn!("{} ({}) оборвал поток: {}", step.provider.name(), options.model, e);
+                    last_error = e;
+                }
+                None => {
+                    warn!("{} ({}) вернул пустой ответ", step.provider.name(), options.model);
+                    last_error = anyhow!("No response from {}", step.provider.name());
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::MockProvider;
+    use std::sync::Mutex;
+
+    enum Reply {
+        Answer(&'static str),
+        Empty,
+        Fail,
+    }
+
+    /// Отвечает заданным образом и запоминает, с какими параметрами его звали.
+    struct Scripted {
+        name: &'static str,
+        reply: Reply,
+        calls: Mutex<Vec<(String, bool)>>,
+    }
+
+    impl Scripted {
+        fn new(name: &'static str, reply: Reply) -> Arc<Self> {
+            Arc::new(Self {
+                name,
+                reply,
+                calls: Mutex::new(Vec::new()),
+            })
+        }
+
+        fn calls(&self) -> Vec<(String, bool)> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl ChatProvider for Scripted {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn chat(&self, _messages: Vec<ChatMessage>, options: &ChatOptions) -> Result<String> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push((options.model.clone(), options.fail_fast_on_rate_limit));
+            match self.reply {
+                Reply::Answer(answer) => Ok(answer.to_string()),
+                Reply::Empty => Ok(String::new()),
+                Reply::Fail => Err(anyhow!("{} failed", self.name)),
+            }
+        }
+    }
+
+    fn step(provider: Arc<dyn ChatProvider>, model: Option<&str>) -> FallbackStep {
+        FallbackStep {
+            provider,
+            model: model.map(str::to_string),
+        }
+    }
+
+    fn question() -> Vec<ChatMessage> {
+        vec![ChatMessage::new("user", "вопрос")]
+    }
+
+    fn options() -> ChatOptions {
+        ChatOptions {
+            model: "session-model".to_string(),
+            ..ChatOptions::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn primary_answer_has_no_note() {
+        let primary = Scripted::new("primary", Reply::Answer("ответ"));
+        let backup = Scripted::new("backup", Reply::Answer("запасной"));
+        let chain = FallbackChain::new(vec![step(primary.clone(), None), step(backup.clone(), Some("small"))]);
+
+        assert_eq!(chain.chat(question(), &options()).await.unwrap(), "ответ");
+        assert!(backup.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn falls_through_in_order() {
+        let primary = Scripted::new("primary", Reply::Fail);
+        let empty = Scripted::new("empty", Reply::Empty);
+        let backup = Scripted::new("backup", Reply::Answer("запасной"));
+        let chain = FallbackChain::new(vec![
+            step(primary.clone(), None),
+            step(empty.clone(), Some("medium")),
+            step(backup.clone(), Some("small")),
+        ]);
+
+        let answer = chain.chat(question(), &options()).await.unwrap();
+        assert_eq!(answer, format!("{}запасной", FALLBACK_NOTE));
+        assert_eq!(primary.calls(), [("session-model".to_string(), true)]);
+        assert_eq!(empty.calls(), [("medium".to_string(), true)]);
+        // Последнему шагу переходить некуда, он пережидает лимит
+        assert_eq!(backup.calls(), [("small".to_string(), false)]);
+    }
+
+    #[tokio::test]
+    async fn single_step_waits_on_rate_limit() {
+        let only = Scripted::new("only", Reply::Answer("ответ"));
+        let chain = FallbackChain::new(vec![step(only.clone(), None)]);
+        chain.chat(question(), &options()).await.unwrap();
+        assert_eq!(only.calls(), [("session-model".to_string(), false)]);
+    }
+
+    #[tokio::test]
+    async fn last_error_is_returned() {
+        let chain = FallbackChain::new(vec![
+            step(Scripted::new("first", Reply::Fail), None),
+            step(Scripted::new("second", Reply::Fail), None),
+        ]);
+        let error = chain.chat(question(), &options()).await.unwrap_err();
+        assert_eq!(error.to_string(), "second failed");
+    }
+
+    #[tokio::test]
+    async fn stream_falls_back_before_first_delta() {
+        let chain = FallbackChain::new(vec![
+            step(Scripted::new("primary", Reply::Fail), None),
+            step(Arc::new(MockProvider::new(String::new())), None),
+        ]);
+        let deltas: Vec<String> = chain
+            .chat_stream(question(), &options())
+            .await
+            .unwrap()
+            .map(|delta| delta.unwrap())
+            .collect()
+            .await;
+        assert_eq!(deltas[0], FALLBACK_NOTE);
+        assert_eq!(deltas.concat(), format!("{}Вы сказали: вопрос", FALLBACK_NOTE));
+    }
+}
@@ -0,0 +1,385 @@
+use std::iter::Peekable;
+
+use crate::display::FontCoverage;
+use crate::render;
+
+const SUPERSCRIPTS: &[(char, char)] = &[
+    ('⁰', '0'),
+    ('¹', '1'),
+    ('²', '2'),
+    ('³', '3'),
+    ('⁴', '4'),
+    ('⁵', '5'),
+    ('⁶', '6'),
+    ('⁷', '7'),
+    ('⁸', '8'),
+    ('⁹', '9'),
+    ('⁺', '+'),
+    ('⁻', '-'),
+    ('⁼', '='),
+    ('⁽', '('),
+    ('⁾', ')'),
+    ('ⁿ', 'n'),
+    ('ⁱ', 'i'),
+    ('ᵃ', 'a'),
+    ('ᵇ', 'b'),
+    ('ᵉ', 'e'),
+    ('ᵏ', 'k'),
+    ('ᵐ', 'm'),
+    ('ᵖ', 'p'),
+    ('ᵗ', 't'),
+    ('ˣ', 'x'),
+    ('ʸ', 'y'),
+];
+
+const SUBSCRIPTS: &[(char, char)] = &[
+    ('₀', '0'),
+    ('₁', '1'),
+    ('₂', '2'),
+    ('₃', '3'),
+    ('₄', '4'),
+    ('₅', '5'),
+    ('₆', '6'),
+    ('₇', '7'),
+    ('₈', '8'),
+    ('₉', '9'),
+    ('₊', '+'),
+    ('₋', '-'),
+    ('₌', '='),
+    ('₍', '('),
+    ('₎', ')'),
+    ('ₐ', 'a'),
+    ('ₑ', 'e'),
+    ('ₓ', 'x'),
+    ('ᵢ', 'i'),
+    ('ⱼ', 'j'),
+    ('ₖ', 'k'),
+    ('ₙ', 'n'),
+];
+
+const FALLBACKS: &[(char, &str)] = &[
+    // Математика
+    ('∫', "int "),
+    ('∬', "int int "),
+    ('∮', "oint "),
+    ('∑', "sum "),
+    ('∏', "prod "),
+    ('∇', "nabla "),
+    ('∂', "d"),
+    ('√', "sqrt "),
+    ('∛', "cbrt "),
+    ('∞', "inf"),
+    ('≈', "~"),
+    ('≃', "~"),
+    ('∼', "~"),
+    ('≡', "=="),
+    ('≠', "!="),
+    ('≤', "<="),
+    ('≥', ">="),
+    ('≪', "<<"),
+    ('≫', ">>"),
+    ('±', "+-"),
+    ('∓', "-+"),
+    ('×', "*"),
+    ('·', "*"),
+    ('⋅', "*"),
+    ('∙', "*"),
+    ('÷', "/"),
+    ('−', "-"),
+    ('∝', "~"),
+    ('∈', " in "),
+    ('∉', " not in "),
+    ('⊂', " subset "),
+    ('⊆', " subset "),
+    ('∪', " U "),
+    ('∩', " n "),
+    ('∅', "{}"),
+    ('∀', "for all "),
+    ('∃', "exists "),
+    ('¬', "not "),
+    ('∧', " and "),
+    ('∨', " or "),
+    ('→', "->"),
+    ('←', "<-"),
+    ('↔', "<->"),
+    ('⇒', "=>"),
+    ('⇐', "<="),
+    ('⇔', "<=>"),
+    ('↑', "^"),
+    ('↓', "v"),
+    ('′', "'"),
+    ('″', "''"),
+    ('‰', " promille"),
+    ('½', "1/2"),
+    ('⅓', "1/3"),
+    ('¼', "1/4"),
+    ('¾', "3/4"),
+    // Греческие буквы
+    ('α', "alpha"),
+    ('β', "beta"),
+    ('γ', "gamma"),
+    ('δ', "delta"),
+    ('ε', "eps"),
+    ('ϵ', "eps"),
+    ('ζ', "zeta"),
+    ('η', "eta"),
+    ('θ', "theta"),
+    ('ι', "iota"),
+    ('κ', "kappa"),
+    ('λ', "lambda"),
+    ('μ', "mu"),
+    ('µ', "mu"),
+    ('ν', "nu"),
+    ('ξ', "xi"),
+    ('ο', "o"),
+    ('π', "pi"),
+    ('ρ', "rho"),
+    ('σ', "sigma"),
+    ('ς', "sigma"),
+    ('τ', "tau"),
+    ('υ', "upsilon"),
+    ('φ', "phi"),
+    ('ϕ', "phi"),
+    ('χ', "chi"),
+    ('ψ', "psi"),
+    ('ω', "omega"),
+    ('Γ', "Gamma"),
+    ('Δ', "Delta"),
+    ('Θ', "Theta"),
+    ('Λ', "Lambda"),
+    ('Ξ', "Xi"),
+    ('Π', "Pi"),
+    ('Σ', "Sigma"),
+    ('Φ', "Phi"),
+    ('Ψ', "Psi"),
+    ('Ω', "Omega"),
+    // Типографика
+    ('—', "-"),
+    ('–', "-"),
+    ('‑', "-"),
+    ('«', "\""),
+    ('»', "\""),
+    ('“', "\""),
+    ('”', "\""),
+    ('„', "\""),
+    ('‘', "'"),
+    ('’', "'"),
+    ('…', "..."),
+    ('•', "-"),
+    ('\u{a0}', " "),
+    ('\u{2009}', " "),
+    ('\u{202f}', " "),
+    ('©', "(c)"),
+    ('®', "(R)"),
+    ('™', "TM"),
+    ('€', "EUR"),
+    ('₽', "руб."),
+    ('№', "N"),
+];
+
+const CYRILLIC_LATIN: &[(char, &str)] = &[
+    ('а', "a"),
+    ('б', "b"),
+    ('в', "v"),
+    ('г', "g"),
+    ('д', "d"),
+    ('е', "e"),
+    ('ё', "yo"),
+    ('ж', "zh"),
+    ('з', "z"),
+    ('и', "i"),
+    ('й', "y"),
+    ('к', "k"),
+    ('л', "l"),
+    ('м', "m"),
+    ('н', "n"),
+    ('о', "o"),
+    ('п', "p"),
+    ('р', "r"),
+    ('с', "s"),
+    ('т', "t"),
+    ('у', "u"),
+    ('ф', "f"),
+    ('х', "kh"),
+    ('ц', "ts"),
+    ('ч', "ch"),
+    ('ш', "sh"),
+    ('щ', "shch"),
+    ('ъ', ""),
+    ('ы', "y"),
+    ('ь', ""),
+    ('э', "e"),
+    ('ю', "yu"),
+    ('я', "ya"),
+];
+
+/// Есть ли символ в шрифте: базовый набор `coverage` плюс `extra`.
+pub fn is_supported(c: char, coverage: FontCoverage, extra: &str) -> bool {
+    let ascii = c == '\n' || c == '\t' || (' '..='~').contains(&c);
+    match coverage {
+        FontCoverage::Unicode => true,
+        _ if ascii || extra.contains(c) => true,
+        FontCoverage::Ascii => false,
+        // Набор u8g2 `_cyrillic`
+        FontCoverage::Cyrillic => ('\u{400}'..='\u{52f}').contains(&c) || c == '№',
+        FontCoverage::Bundled => render::has_glyph(c),
+    }
+}
+
+/// Заменяет символы, которых нет в шрифте устройства, читаемыми заменами:
+/// "∫" → "int", "H₂O" → "H2O", "x²" → "x^2", кириллица → латиница для ASCII-шрифтов.
+/// Что заменить нечем (эмодзи и т.п.), выбрасывается.
+pub fn fit_to_font(text: &str, coverage: FontCoverage, extra: &str) -> String {
+    if coverage == FontCoverage::Unicode {
+        return text.to_string();
+    }
+
+    let supported = |c: char| is_supported(c, coverage, extra);
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if supported(c) {
+            out.push(c);
+        } else if lookup(SUPERSCRIPTS, c).is_some() {
+            let script = take_script(c, &mut chars, SUPERSCRIPTS, supported);
+            if script.chars().count() > 1 && !script.chars().all(|c| c.is_ascii_digit()) {
+                out.push_str(&format!("^({})", script));
+            } else {
+                out.push('^');
+                out.push_str(&script);
+            }
+        } else if lookup(SUBSCRIPTS, c).is_some() {
+            out.push_str(&take_script(c, &mut chars, SUBSCRIPTS, supported));
+        } else if c == '°' {
+            // "20°C" → "20 C", "90°" → "90 град"
+            let scale = chars.peek().is_some_and(|next| matches!(next, 'C' | 'F' | 'С' | 'K'));
+            let fallback = fit_to_font(if scale { " " } else { " град" }, coverage, extra);
+            push_spaced(&mut out, &fallback, chars.peek());
+        } else if let Some(fallback) = lookup(FALLBACKS, c) {
+            // Замены сами из ASCII и кириллицы, для ASCII-шрифта кириллица транслитерируется
+            push_spaced(&mut out, &fit_to_font(fallback, coverage, extra), chars.peek());
+        } else if let Some(latin) = transliterate(c) {
+            out.push_str(&latin);
+        } else if c.is_alphanumeric() {
+            out.push('?');
+        } else if out.ends_with(' ') && chars.peek() == Some(&' ') {
+            chars.next();
+        }
+    }
+
+    out
+}
+
+/// Добавляет замену без двойных пробелов по краям.
+fn push_spaced(out: &mut String, fallback: &str, next: Option<&char>) {
+    let mut fallback = fallback;
+    if out.is_empty() || out.ends_with(char::is_whitespace) {
+        fallback = fallback.trim_start();
+    }
+    if next.is_none_or(|c| c.is_whitespace() || c.is_ascii_punctuation()) {
+        fallback = fallback.trim_end();
+    }
+    out.push_str(fallback);
+}
+
+fn lookup<T: Copy>(table: &[(char, T)], c: char) -> Option<T> {
+    table.iter().find(|(from, _)| *from == c).map(|(_, to)| *to)
+}
+
+/// Забирает подряд идущие над- или подстрочные символы, начиная с `first`.
+fn take_script(
+    first: char,
+    chars: &mut Peekable<impl Iterator<Item = char>>,
+    table: &[(char, char)],
+    supported: impl Fn(char) -> bool,
+) -> String {
+    let mut script = String::new();
+    script.extend(lookup(table, first));
+    while let Some(&next) = chars.peek() {
+        match lookup(table, next).filter(|_| !supported(next)) {
+            Some(plain) => {
+                script.push(plain);
+                chars.next();
+            }
+            None => break,
+        }
+    }
+    script
+}
+
+fn transliterate(c: char) -> Option<String> {
+    let lower = c.to_lowercase().next()?;
+    let latin = lookup(CYRILLIC_LATIN, lower)?;
+    if lower == c {
+        return Some(latin.to_string());
+    }
+    let mut upper = latin.chars();
+    Some(upper.next().map(|first| first.to_ascii_uppercase().to_string() + upper.as_str()).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(coverage: FontCoverage, cases: &[(&str, &str)]) {
+        for (input, expected) in cases {
+            assert_eq!(fit_to_font(input, coverage, ""), *expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn replaces_scripts() {
+        check(
+            FontCoverage::Cyrillic,
+            &[
+                ("x² + y²", "x^2 + y^2"),
+                ("x¹²", "x^12"),
+                ("10⁻³", "10^(-3)"),
+                ("eⁱᵖ", "e^(ip)"),
+                ("H₂O", "H2O"),
+                ("aₙ₊₁", "an+1"),
+            ],
+        );
+    }
+
+    #[test]
+    fn replaces_degree_sign() {
+        check(
+            FontCoverage::Cyrillic,
+            &[("20°C", "20 C"), ("90°", "90 град"), ("угол 90° прямой", "угол 90 град прямой")],
+        );
+        check(FontCoverage::Ascii, &[("90°", "90 grad"), ("-5°С", "-5 S")]);
+    }
+
+    #[test]
+    fn falls_back_to_readable_text() {
+        check(
+            FontCoverage::Cyrillic,
+            &[
+                ("∫ f dx", "int f dx"),
+                ("a ≤ b", "a <= b"),
+                ("2πr", "2pir"),
+                ("«Да» — нет", "\"Да\" - нет"),
+                ("ок 👍 да", "ок да"),
+                ("中", "?"),
+            ],
+        );
+    }
+
+    #[test]
+    fn transliterates_for_ascii_fonts() {
+        check(
+            FontCoverage::Ascii,
+            &[("Привет, мир", "Privet, mir"), ("Щука и Ёж", "Shchuka i Yozh"), ("₽", "rub.")],
+        );
+    }
+
+    #[test]
+    fn keeps_supported_characters() {
+        assert_eq!(fit_to_font("α ≈ β", FontCoverage::Cyrillic, "α≈"), "α ≈ beta");
+        assert_eq!(fit_to_font("x² ∫ 👍", FontCoverage::Unicode, ""), "x² ∫ 👍");
+        assert_eq!(fit_to_font("№ 5", FontCoverage::Cyrillic, ""), "№ 5");
+        assert_eq!(fit_to_font("№ 5", FontCoverage::Ascii, ""), "N 5");
+    }
+}
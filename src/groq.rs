@@ -1,23 +1,46 @@
-use reqwest::{multipart, Client};
+use reqwest::{multipart, Client, RequestBuilder};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use serde_json::Value;
+use std::{collections::VecDeque, mem, path::Path, pin::Pin, sync::Arc};
 use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures_util::{stream, Stream, StreamExt};
 
-#[derive(Serialize, Deserialize)]
-struct ChatMessage {
-    role: String,
-    content: String,
-}
+use crate::provider::{
+    ChatMessage, ChatOptions, ChatProvider, DeltaStream, Transcription, TranscriptionOptions,
+    TranscriptionProvider,
+};
+use crate::tools::{self, FunctionCall, ToolCall};
+use crate::retry::{send_with_retry, CircuitBreaker, CONNECT_TIMEOUT, READ_TIMEOUT};
+
+// Сколько раз подряд модель может звать инструменты до финального ответа
+const MAX_TOOL_ROUNDS: usize = 5;
 
 #[derive(Serialize)]
 struct ChatRequest {
     model: String,
     messages: Vec<ChatMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Value>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+}
+
+#[derive(Deserialize)]
+struct ResponseMessage {
+    content: Option<String>,
+    tool_calls: Option<Vec<ToolCall>>,
 }
 
 #[derive(Deserialize)]
 struct ChatChoice {
-    message: ChatMessage,
+    message: ResponseMessage,
 }
 
 #[derive(Deserialize)]
@@ -26,218 +49,357 @@ struct ChatResponse {
 }
 
 #[derive(Deserialize)]
-struct TranscriptionResponse {
-    text: String,
+struct FunctionDelta {
+    name: Option<String>,
+    arguments: Option<String>,
 }
 
+#[derive(Deserialize)]
+struct ToolCallDelta {
+    #[serde(default)]
+    index: usize,
+    id: Option<String>,
+    function: Option<FunctionDelta>,
+}
+
+#[derive(Deserialize)]
+struct StreamDelta {
+    content: Option<String>,
+    tool_calls: Option<Vec<ToolCallDelta>>,
+}
+
+#[derive(Deserialize)]
+struct StreamChoice {
+    delta: StreamDelta,
+}
+
+#[derive(Deserialize)]
+struct StreamChunk {
+    choices: Vec<StreamChoice>,
+}
+
+enum StreamEvent {
+    Text(String),
+    ToolCall(ToolCallDelta),
+}
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<StreamEvent>> + Send>>;
+
+fn chat_request(messages: Vec<ChatMessage>, options: &ChatOptions, round: usize) -> ChatRequest {
+    // В последнем раунде инструменты не даём, чтобы модель ответила
+    let tools = (options.tools && round + 1 < MAX_TOOL_ROUNDS).then(tools::definitions);
+    ChatRequest {
+        model: options.model.clone(),
+        messages,
+        temperature: options.temperature,
+        max_tokens: options.max_tokens,
+        top_p: options.top_p,
+        tools,
+        stream: false,
+    }
+}
+
+/// Выполняет вызовы инструментов и дописывает их результаты в диалог.
+fn run_tools(messages: &mut Vec<ChatMessage>, content: String, calls: Vec<ToolCall>) {
+    let results: Vec<ChatMessage> = calls
+        .iter()
+        .map(|call| ChatMessage::tool_result(&call.id, tools::run(call)))
+        .collect();
+    messages.push(ChatMessage::tool_calls(content, calls));
+    messages.extend(results);
+}
+
+/// Дописывает текст раунда к ответу; раунды разделяются переводом строки.
+fn append_round(answer: &mut String, content: &str) {
+    if content.is_empty() {
+        return;
+    }
+    if !answer.is_empty() {
+        answer.push('\n');
+    }
+    answer.push_str(content);
+}
+
+/// Собирает вызовы инструментов из кусков потока.
+fn merge_tool_call(calls: &mut Vec<ToolCall>, delta: ToolCallDelta) {
+    while calls.len() <= delta.index {
+        calls.push(ToolCall {
+            id: String::new(),
+            kind: "function".to_string(),
+            function: FunctionCall {
+                name: String::new(),
+                arguments: String::new(),
+            },
+        });
+    }
+    let call = &mut calls[delta.index];
+    if let Some(id) = delta.id {
+        call.id = id;
+    }
+    if let Some(function) = delta.function {
+        call.function.name.push_str(&function.name.unwrap_or_default());
+        call.function.arguments.push_str(&function.arguments.unwrap_or_default());
+    }
+}
+
+/// Разбирает SSE-поток `data: {...}` в куски текста и вызовов инструментов до `data: [DONE]`.
+fn sse_events(response: reqwest::Response) -> EventStream {
+    let state = (response.bytes_stream(), Vec::new(), VecDeque::new(), false);
+
+    Box::pin(stream::unfold(state, |(mut bytes, mut buffer, mut pending, mut done)| async move {
+        loop {
+            if let Some(event) = pending.pop_front() {
+                return Some((Ok(event), (bytes, buffer, pending, done)));
+            }
+            if done {
+                return None;
+            }
+
+            match bytes.next().await {
+                Some(Ok(chunk)) => {
+                    buffer.extend_from_slice(&chunk);
+                    // Разбираем только целые строки, чтобы не резать UTF-8
+                    while let Some(pos) = buffer.iter().position(|&b| b == b'\n') {
+                        let line: Vec<u8> = buffer.drain(..=pos).collect();
+                        let line = String::from_utf8_lossy(&line);
+                        let Some(data) = line.trim().strip_prefix("data:") else {
+                            continue;
+                        };
+                        let data = data.trim();
+                        if data == "[DONE]" {
+                            done = true;
+                            break;
+                        }
+                        match serde_json::from_str::<StreamChunk>(data) {
+                            Ok(chunk) => {
+                                for choice in chunk.choices {
+                                    if let Some(content) = choice.delta.content.filter(|c| !c.is_empty()) {
+                                        pending.push_back(StreamEvent::Text(content));
+                                    }
+                                    for call in choice.delta.tool_calls.unwrap_or_default() {
+                                        pending.push_back(StreamEvent::ToolCall(call));
+                                    }
+                                }
+                            }
+                            Err(_) => {
+                                done = true;
+                                let error = anyhow!("Stream error: {}", data);
+                                return Some((Err(error), (bytes, buffer, pending, done)));
+                            }
+                        }
+                    }
+                }
+                Some(Err(e)) => {
+                    done = true;
+                    return Some((Err(e.into()), (bytes, buffer, pending, done)));
+                }
+                None => done = true,
+            }
+        }
+    }))
+}
+
+/// Клиент OpenAI-совместимого API: Groq по умолчанию или любой `base_url`.
+#[derive(Clone)]
 pub struct GroqClient {
     client: Client,
+    name: String,
+    base_url: String,
     api_key: String,
+    breaker: Arc<CircuitBreaker>,
 }
 
 impl GroqClient {
     pub fn new(api_key: String) -> Self {
+        Self::openai_compatible("Groq", "https://api.groq.com/openai/v1".to_string(), api_key)
+    }
+
+    pub fn openai_compatible(name: &str, base_url: String, api_key: String) -> Self {
+        let client = Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .read_timeout(READ_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+
         Self {
-            client: Client::new(),
+            client,
+            name: format!("{} ({})", name, base_url),
+            base_url: base_url.trim_end_matches('/').to_string(),
             api_key,
+            breaker: Arc::default(),
         }
     }
 
-    pub async fn get_chat_response(&self, text: &str) -> Result<String> {
-        let request = ChatRequest {
-            model: "openai/gpt-oss-120b".to_string(),
-            messages: vec![
-                ChatMessage {
-                    role: "system".to_string(),
-                    content: "СТРОГО ЗАПРЕЩЕНО использовать LaTeX! Пиши формулы обычным текстом с Unicode символами.
-
-ЗАПРЕЩЁННЫЕ КОМАНДЫ (НИКОГДА не используй):
-\\frac, \\dfrac, \\tfrac, \\left, \\right, \\begin, \\end, ^{}, _{}
-
-ПРАВИЛЬНЫЕ ПРИМЕРЫ ФОРМУЛ:
-❌ НЕПРАВИЛЬНО: r_s = \\dfrac{2GM}{c^2}
-✅ ПРАВИЛЬНО: r_s = 2GM/c^2
-
-❌ НЕПРАВИЛЬНО: ds^{2} = -(1-r_s/r)c^{2}dt^{2}
-✅ ПРАВИЛЬНО: ds^2 = -(1-r_s/r)c^2·dt^2
-
-❌ НЕПРАВИЛЬНО: E = mc^{2}
-✅ ПРАВИЛЬНО: E = mc^2
-
-❌ НЕПРАВИЛЬНО: S = \\pi r^{2}
-✅ ПРАВИЛЬНО: S = πr^2
-
-Ты голосовой AI-ассистент на ESP32 с OLED 128x64. Создан двумя школьниками-вундеркиндами. Портирован на Arduino, находишься в коробке.
-
-ТЕХНИЧЕСКИЕ ХАРАКТЕРИСТИКИ:
-- Микроконтроллер: ESP32
-- Дисплей: OLED 128x64, кириллица, до 99 строк с прокруткой
-- Микрофон: I2S INMP441
-- Связь: WiFi, WebSocket SSL
-- Распознавание: Groq Whisper-large-v3
-- Кнопка управления: короткое нажатие = прокрутка, длинное = запись
-
-ПРАВИЛА ОТВЕТОВ:
-1. Отвечай кратко - максимум 90 строк (обычно 3-5 предложений)
-2. Используй простые слова, избегай сложных терминов
-3. Если просят повторить - повторяй без возражений
-4. Отвечай на русском языке обычным текстом
-5. Будь дружелюбным и полезным помощником
-6. Если пользователь просит перейти на азбуку Морзе - ответь: /morse
-7. НИКОГДА не отвечай азбукой Морзе (точками и тире) - только обычным текстом!
-
-UNICODE СИМВОЛЫ (используй их напрямую):
-π α β γ δ θ λ μ σ ω Ω Δ Σ ∞ ∫ √ ± × · ÷ ≤ ≥ ≠ ≈ ∈ ∂ ∇ →
-
-Дроби: a/b (НЕ \\frac)
-Степени: x^2 (НЕ x^{2})
-Индексы: x_i (НЕ x_{i})".to_string(),
-                },
-                ChatMessage {
-                    role: "user".to_string(),
-                    content: text.to_string(),
-                },
-            ],
-        };
-
-        self.send_chat_request(request).await
-    }
-
-    pub async fn get_chat_response_with_context(
-        &self, 
-        text: &str, 
-        conversation_history: &[(String, String)]
-    ) -> Result<String> {
-        let mut messages = vec![
-            ChatMessage {
-                role: "system".to_string(),
-                content: "СТРОГО ЗАПРЕЩЕНО использовать LaTeX! Пиши формулы обычным текстом с Unicode символами.
-
-ЗАПРЕЩЁННЫЕ КОМАНДЫ (НИКОГДА не используй):
-\\frac, \\dfrac, \\tfrac, \\left, \\right, \\begin, \\end, ^{}, _{}
-
-ПРАВИЛЬНЫЕ ПРИМЕРЫ ФОРМУЛ:
-❌ НЕПРАВИЛЬНО: r_s = \\dfrac{2GM}{c^2}
-✅ ПРАВИЛЬНО: r_s = 2GM/c^2
-
-❌ НЕПРАВИЛЬНО: ds^{2} = -(1-r_s/r)c^{2}dt^{2}
-✅ ПРАВИЛЬНО: ds^2 = -(1-r_s/r)c^2·dt^2
-
-❌ НЕПРАВИЛЬНО: E = mc^{2}
-✅ ПРАВИЛЬНО: E = mc^2
-
-❌ НЕПРАВИЛЬНО: S = \\pi r^{2}
-✅ ПРАВИЛЬНО: S = πr^2
-
-Ты голосовой AI-ассистент на ESP32 с OLED 128x64. Создан двумя школьниками-вундеркиндами. Портирован на Arduino, находишься в коробке.
-
-ТЕХНИЧЕСКИЕ ХАРАКТЕРИСТИКИ:
-- Микроконтроллер: ESP32
-- Дисплей: OLED 128x64, кириллица, до 99 строк с прокруткой
-- Микрофон: I2S INMP441
-- Связь: WiFi, WebSocket SSL
-- Распознавание: Groq Whisper-large-v3
-- Кнопка управления: короткое нажатие = прокрутка, длинное = запись
-
-ПРАВИЛА ОТВЕТОВ:
-1. Отвечай кратко - максимум 90 строк (обычно 3-5 предложений)
-2. Используй простые слова, избегай сложных терминов
-3. Если просят повторить - повторяй без возражений
-4. Отвечай на русском языке обычным текстом
-5. Помни контекст разговора
-6. Будь дружелюбным и полезным помощником
-7. Если пользователь просит перейти на азбуку Морзе - ответь: /morse
-8. НИКОГДА не отвечай азбукой Морзе (точками и тире) - только обычным текстом!
-
-UNICODE СИМВОЛЫ (используй их напрямую):
-π α β γ δ θ λ μ σ ω Ω Δ Σ ∞ ∫ √ ± × · ÷ ≤ ≥ ≠ ≈ ∈ ∂ ∇ →
-
-Дроби: a/b (НЕ \\frac)
-Степени: x^2 (НЕ x^{2})
-Индексы: x_i (НЕ x_{i})".to_string(),
-            }
-        ];
-        
-        let recent_history = if conversation_history.len() > 5 {
-            &conversation_history[conversation_history.len() - 5..]
+    fn post(&self, path: &str) -> RequestBuilder {
+        let request = self.client.post(format!("{}{}", self.base_url, path));
+        // Локальным серверам (Ollama, llama.cpp) ключ обычно не нужен
+        if self.api_key.is_empty() {
+            request
         } else {
-            conversation_history
-        };
+            request.header("Authorization", format!("Bearer {}", self.api_key))
+        }
+    }
+
+    async fn send_chat_request(&self, request: &ChatRequest, options: &ChatOptions) -> Result<reqwest::Response> {
+        send_with_retry(&self.breaker, !options.fail_fast_on_rate_limit, || {
+            Ok(self
+                .post("/chat/completions")
+                .header("Content-Type", "application/json")
+                .json(request))
+        })
+        .await
+    }
+
+    async fn open_stream(&self, messages: &[ChatMessage], options: &ChatOptions, round: usize) -> Result<EventStream> {
+        let mut request = chat_request(messages.to_vec(), options, round);
+        request.stream = true;
+        let response = self.send_chat_request(&request, options).await?;
+        Ok(sse_events(response))
+    }
+}
+
+/// Раунд потокового ответа: текст сразу уходит клиенту, вызовы инструментов копятся
+/// до конца раунда, после чего выполняются и запрос повторяется.
+struct ToolRound {
+    client: GroqClient,
+    messages: Vec<ChatMessage>,
+    options: ChatOptions,
+    round: usize,
+    events: EventStream,
+    /// Текст этого раунда
+    content: String,
+    calls: Vec<ToolCall>,
+    written: bool,
+}
 
-        for (user_msg, assistant_msg) in recent_history {
-            messages.push(ChatMessage {
-                role: "user".to_string(),
-                content: user_msg.clone(),
-            });
-            messages.push(ChatMessage {
-                role: "assistant".to_string(),
-                content: assistant_msg.clone(),
-            });
+fn tool_stream(round: ToolRound) -> DeltaStream {
+    Box::pin(stream::unfold(Some(round), |state| async move {
+        let mut state = state?;
+        loop {
+            match state.events.next().await {
+                Some(Ok(StreamEvent::Text(text))) => {
+                    // Текст следующего раунда - с новой строки, как в `chat`
+                    let delta = if state.content.is_empty() && state.written {
+                        format!("\n{}", text)
+                    } else {
+                        text.clone()
+                    };
+                    state.content.push_str(&text);
+                    state.written = true;
+                    return Some((Ok(delta), Some(state)));
+                }
+                Some(Ok(StreamEvent::ToolCall(delta))) => merge_tool_call(&mut state.calls, delta),
+                Some(Err(e)) => return Some((Err(e), None)),
+                None if state.calls.is_empty() => return None,
+                None => {
+                    let no_response = anyhow!("No response from {}", state.client.name);
+                    if state.round + 1 >= MAX_TOOL_ROUNDS {
+                        return Some((Err(no_response), None));
+                    }
+                    run_tools(&mut state.messages, mem::take(&mut state.content), mem::take(&mut state.calls));
+                    state.round += 1;
+                    match state.client.open_stream(&state.messages, &state.options, state.round).await {
+                        Ok(events) => state.events = events,
+                        Err(e) => return Some((Err(e), None)),
+                    }
+                }
+            }
         }
-        messages.push(ChatMessage {
-            role: "user".to_string(),
-            content: text.to_string(),
-        });
+    }))
+}
 
-        let request = ChatRequest {
-            model: "openai/gpt-oss-120b".to_string(),
-            messages,
-        };
+#[async_trait]
+impl ChatProvider for GroqClient {
+    fn name(&self) -> &str {
+        &self.name
+    }
 
-        self.send_chat_request(request).await
+    async fn chat(&self, mut messages: Vec<ChatMessage>, options: &ChatOptions) -> Result<String> {
+        let mut answer = String::new();
+        for round in 0..MAX_TOOL_ROUNDS {
+            let request = chat_request(messages.clone(), options, round);
+            let response = self.send_chat_request(&request, options).await?;
+            let chat_response: ChatResponse = response.json().await?;
+            let message = chat_response
+                .choices
+                .into_iter()
+                .next()
+                .map(|choice| choice.message)
+                .ok_or_else(|| anyhow!("No response from {}", self.name))?;
+
+            let content = message.content.unwrap_or_default();
+            append_round(&mut answer, &content);
+            match message.tool_calls.filter(|calls| !calls.is_empty()) {
+                Some(calls) => run_tools(&mut messages, content, calls),
+                None if answer.is_empty() => return Err(anyhow!("No response from {}", self.name)),
+                None => return Ok(answer),
+            }
+        }
+        Err(anyhow!("No response from {}", self.name))
     }
 
-    async fn send_chat_request(&self, request: ChatRequest) -> Result<String> {
-        let response = self
-            .client
-            .post("https://api.groq.com/openai/v1/chat/completions")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .header("Content-Type", "application/json")
-            .json(&request)
-            .send()
-            .await?;
+    /// Вызовы инструментов могут прийти и до текста, и после него: в конце раунда
+    /// они выполняются, и поток продолжается ответом следующего раунда.
+    /// До первого куска текста ошибки возвращаются сразу, чтобы цепочка могла переключиться.
+    async fn chat_stream(&self, messages: Vec<ChatMessage>, options: &ChatOptions) -> Result<DeltaStream> {
+        let events = self.open_stream(&messages, options, 0).await?;
+        let mut deltas = tool_stream(ToolRound {
+            client: self.clone(),
+            messages,
+            options: options.clone(),
+            round: 0,
+            events,
+            content: String::new(),
+            calls: Vec::new(),
+            written: false,
+        });
 
-        if !response.status().is_success() {
-            let error_text = response.text().await?;
-            return Err(anyhow!("Groq API error: {}", error_text));
+        match deltas.next().await {
+            Some(Ok(first)) => Ok(Box::pin(stream::once(async move { Ok(first) }).chain(deltas))),
+            Some(Err(e)) => Err(e),
+            None => Err(anyhow!("No response from {}", self.name)),
         }
+    }
+}
 
-        let chat_response: ChatResponse = response.json().await?;
-        
-        chat_response
-            .choices
-            .first()
-            .map(|choice| choice.message.content.clone())
-            .ok_or_else(|| anyhow!("No response from Groq"))
+#[async_trait]
+impl TranscriptionProvider for GroqClient {
+    fn name(&self) -> &str {
+        &self.name
     }
 
-    pub async fn transcribe_audio(&self, audio_path: &Path) -> Result<String> {
+    async fn transcribe(&self, audio_path: &Path, options: &TranscriptionOptions) -> Result<Transcription> {
         let file_bytes = tokio::fs::read(audio_path).await?;
         
-        let form = multipart::Form::new()
-            .text("model", "whisper-large-v3")
-            .text("language", "ru")
-            .part(
+        // Форму нельзя переиспользовать, поэтому собираем её заново на каждую попытку
+        let build_form = || -> Result<multipart::Form> {
+            let mut form = multipart::Form::new()
+                .text("model", options.model.clone())
+                .text("response_format", "verbose_json")
+                .text("timestamp_granularities[]", "segment");
+            if let Some(language) = &options.language {
+                form = form.text("language", language.clone());
+            }
+            if let Some(prompt) = options.full_prompt() {
+                form = form.text("prompt", prompt);
+            }
+            if let Some(temperature) = options.temperature {
+                form = form.text("temperature", temperature.to_string());
+            }
+            Ok(form.part(
                 "file",
-                multipart::Part::bytes(file_bytes)
+                multipart::Part::bytes(file_bytes.clone())
                     .file_name("audio.wav")
                     .mime_str("audio/wav")?,
-            );
-
-        let response = self
-            .client
-            .post("https://api.groq.com/openai/v1/audio/transcriptions")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .multipart(form)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            let error_text = response.text().await?;
-            return Err(anyhow!("Groq transcription error: {}", error_text));
-        }
+            ))
+        };
+
+        let response = send_with_retry(&self.breaker, true, || {
+            Ok(self.post("/audio/transcriptions").multipart(build_form()?))
+        })
+        .await?;
 
-        let transcription: TranscriptionResponse = response.json().await?;
-        Ok(transcription.text)
+        let transcription: Transcription = response.json().await?;
+        Ok(transcription)
     }
 }
@@ -0,0 +1,210 @@
+use std::env;
+use tracing::{info, warn};
+
+use crate::audio::AudioStats;
+use crate::provider::Transcription;
+
+const DEFAULT_PHRASES: &str = include_str!("../hallucinations.txt");
+
+// Whisper уверен, что в записи нет речи
+const DEFAULT_MAX_NO_SPEECH_PROB: f32 = 0.6;
+// При слабом сигнале хватает и меньшей уверенности
+const WEAK_SIGNAL_NO_SPEECH_PROB: f32 = 0.3;
+const WEAK_SIGNAL_SNR_DB: f32 = 10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuspectReason {
+    Empty,
+    KnownPhrase,
+    NoSpeech,
+    WeakSignal,
+}
+
+pub struct HallucinationFilter {
+    phrases: Vec<String>,
+    max_no_speech_prob: f32,
+}
+
+impl HallucinationFilter {
+    /// Список фраз берётся из `HALLUCINATIONS_PATH`, иначе встроенный `hallucinations.txt`.
+    pub fn from_env() -> Self {
+        let phrases = match env::var("HALLUCINATIONS_PATH") {
+            Ok(path) => match std::fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(e) => {
+                    warn!("Не удалось прочитать {}: {}, используем встроенный список", path, e);
+                    DEFAULT_PHRASES.to_string()
+                }
+            },
+            Err(_) => DEFAULT_PHRASES.to_string(),
+        };
+
+        let max_no_speech_prob = env::var("MAX_NO_SPEECH_PROB")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_NO_SPEECH_PROB);
+
+        let filter = Self::new(&phrases, max_no_speech_prob);
+        info!("Загружено {} фраз-галлюцинаций Whisper", filter.phrases.len());
+        filter
+    }
+
+    pub fn new(phrases: &str, max_no_speech_prob: f32) -> Self {
+        let phrases = phrases
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(normalize)
+            .filter(|phrase| !phrase.is_empty())
+            .collect();
+
+        Self {
+            phrases,
+            max_no_speech_prob,
+        }
+    }
+
+    /// Возвращает причину, если распознанный текст похож на галлюцинацию.
+    pub fn check(&self, transcription: &Transcription, stats: &AudioStats) -> Option<SuspectReason> {
+        let text = normalize(&transcription.text);
+        if text.is_empty() {
+            return Some(SuspectReason::Empty);
+        }
+
+        // Фраза из списка занимает большую часть текста
+        let text_len = text.chars().count();
+        let known_phrase = self.phrases.iter().any(|phrase| {
+            text.contains(phrase.as_str()) && phrase.chars().count() * 2 >= text_len
+        });
+        if known_phrase {
+            return Some(SuspectReason::KnownPhrase);
+        }
+
+        let no_speech_prob = transcription.no_speech_prob();
+        if no_speech_prob > self.max_no_speech_prob {
+            return Some(SuspectReason::NoSpeech);
+        }
+
+        if stats.snr_db < WEAK_SIGNAL_SNR_DB && no_speech_prob > WEAK_SIGNAL_NO_SPEECH_PROB {
+            return Some(SuspectReason::WeakSignal);
+        }
+
+        None
+    }
+}
+
+fn normalize(text: &str) -> String {
+    text.to_lowercase()
+        .replace('ё', "е")
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::AudioVerdict;
+    use crate::provider::TranscriptionSegment;
+
+    fn filter() -> HallucinationFilter {
+        HallucinationFilter::new(DEFAULT_PHRASES, DEFAULT_MAX_NO_SPEECH_PROB)
+    }
+
+    fn stats(snr_db: f32) -> AudioStats {
+        AudioStats {
+            rms: 0.1,
+            rms_dbfs: -20.0,
+            peak: 0.5,
+            clipping_ratio: 0.0,
+            snr_db,
+            duration_secs: 2.0,
+            speech_secs: 1.5,
+            verdict: AudioVerdict::Ok,
+        }
+    }
+
+    /// Сегменты `(длительность, no_speech_prob)` подряд.
+    fn transcription(text: &str, segments: &[(f32, f32)]) -> Transcription {
+        let mut start = 0.0;
+        let segments = segments
+            .iter()
+            .map(|&(duration, no_speech_prob)| {
+                let segment = TranscriptionSegment {
+                    id: 0,
+                    start,
+                    end: start + duration,
+                    text: String::new(),
+                    avg_logprob: -0.3,
+                    no_speech_prob,
+                    compression_ratio: 1.0,
+                };
+                start += duration;
+                segment
+            })
+            .collect();
+        Transcription {
+            text: text.to_string(),
+            language: Some("ru".to_string()),
+            duration: Some(start),
+            segments,
+        }
+    }
+
+    #[test]
+    fn known_phrase_is_suspect() {
+        let text = transcription("Продолжение следует...", &[(2.0, 0.1)]);
+        assert_eq!(filter().check(&text, &stats(30.0)), Some(SuspectReason::KnownPhrase));
+        let text = transcription("Спасибо за просмотр!", &[(2.0, 0.1)]);
+        assert_eq!(filter().check(&text, &stats(30.0)), Some(SuspectReason::KnownPhrase));
+    }
+
+    #[test]
+    fn phrase_inside_longer_question_passes() {
+        let text = transcription(
+            "Почему в конце сериала пишут продолжение следует, если сезон последний и больше серий не будет?",
+            &[(6.0, 0.1)],
+        );
+        assert_eq!(filter().check(&text, &stats(30.0)), None);
+    }
+
+    #[test]
+    fn short_real_answer_passes() {
+        let text = transcription("Да.", &[(0.5, 0.2)]);
+        assert_eq!(filter().check(&text, &stats(30.0)), None);
+    }
+
+    #[test]
+    fn empty_text_is_suspect() {
+        let text = transcription(" ... ", &[(1.0, 0.1)]);
+        assert_eq!(filter().check(&text, &stats(30.0)), Some(SuspectReason::Empty));
+    }
+
+    #[test]
+    fn no_speech_threshold_is_exclusive() {
+        let at_threshold = transcription("Сколько будет два плюс два", &[(2.0, 0.6)]);
+        assert_eq!(filter().check(&at_threshold, &stats(30.0)), None);
+        let above = transcription("Сколько будет два плюс два", &[(2.0, 0.61)]);
+        assert_eq!(filter().check(&above, &stats(30.0)), Some(SuspectReason::NoSpeech));
+    }
+
+    #[test]
+    fn no_speech_is_weighted_by_duration() {
+        // Короткий тихий сегмент не перевешивает длинный с речью: (0.9 + 3 * 0.4) / 4 = 0.525
+        let mostly_speech = transcription("Сколько будет два плюс два", &[(1.0, 0.9), (3.0, 0.4)]);
+        assert_eq!(filter().check(&mostly_speech, &stats(30.0)), None);
+        // (1.0 + 3 * 0.5) / 4 = 0.625
+        let mostly_silence = transcription("Сколько будет два плюс два", &[(1.0, 1.0), (3.0, 0.5)]);
+        assert_eq!(filter().check(&mostly_silence, &stats(30.0)), Some(SuspectReason::NoSpeech));
+    }
+
+    #[test]
+    fn weak_signal_lowers_threshold() {
+        let text = transcription("Сколько будет два плюс два", &[(2.0, 0.4)]);
+        assert_eq!(filter().check(&text, &stats(30.0)), None);
+        assert_eq!(filter().check(&text, &stats(5.0)), Some(SuspectReason::WeakSignal));
+    }
+}
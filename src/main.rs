@@ -1,26 +1,98 @@
 use axum::{
-    extract::{ws::WebSocket, WebSocketUpgrade},
-    response::{IntoResponse, Json},
-    routing::{get, any},
+    extract::{ws::WebSocket, Path, Query, State, WebSocketUpgrade},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::{any, delete, get},
     Router,
 };
-use serde::Serialize;
-use std::{env, net::SocketAddr};
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
 use tower_http::{cors::CorsLayer, services::ServeDir};
+use futures_util::StreamExt;
 use tracing::{error, info};
 
 mod groq;
 mod audio;
+mod auth;
+mod calc;
+mod context;
+mod display;
+mod error;
+mod fallback;
+mod glyphs;
+mod hallucination;
+mod mode;
 mod morse;
+mod plaintext;
+mod prompt;
+mod protocol;
+mod provider;
+mod queue;
+mod render;
+mod retry;
+mod session;
+mod settings;
+mod storage;
+mod summary;
+mod tools;
+mod tts;
+mod units;
 
-use groq::GroqClient;
-use audio::save_raw_as_wav;
+use provider::{
+    chat_provider_from_env, transcription_provider_from_env, ChatOptions,
+    ChatProvider, TranscriptionOptions, TranscriptionProvider,
+};
+use context::{context_messages, ContextBudget, ConversationHistory};
+use display::{DisplayProfile, Pager};
+use error::AssistantError;
+use auth::{Auth, LiveConnection, LiveConnections};
+use audio::{analyze_audio, save_raw_as_wav, AudioAggregate, AudioStats, AudioSummary};
+use hallucination::HallucinationFilter;
+use mode::Mode;
 use morse::decode_morse;
+use plaintext::{to_plain_text, PlainTextStream};
+use prompt::PromptStore;
+use protocol::{ClientMessage, Connection, Outbox, ServerMessage, SpeechHeader, Status, PROTOCOL_VERSION};
+use queue::{Job, JobQueue};
+use session::{Session, SessionStore};
+use settings::SessionSettings;
+use storage::{ConversationStore, Device, DeviceStore, Source};
+use summary::Summarizer;
+use tts::{OpenAiSpeechClient, SpeechFormat, TtsProvider};
+
+const SPEECH_CHUNK_SIZE: usize = 4096;
+// Сколько вопросов может ждать в очереди сессии, пока выполняется текущий
+const MAX_QUEUED_JOBS: usize = 2;
+const MIN_REQUEST_INTERVAL_SECS: u64 = 5;
+
+#[derive(Clone)]
+struct AppState {
+    chat: Arc<dyn ChatProvider>,
+    transcriber: Arc<dyn TranscriptionProvider>,
+    audio_stats: Arc<Mutex<AudioAggregate>>,
+    hallucination_filter: Arc<HallucinationFilter>,
+    default_settings: Arc<SessionSettings>,
+    context_budget: Arc<ContextBudget>,
+    summarizer: Option<Arc<Summarizer>>,
+    prompts: Arc<PromptStore>,
+    tts: Arc<dyn TtsProvider>,
+    sessions: Arc<SessionStore>,
+    storage: Arc<dyn ConversationStore>,
+    devices: Arc<dyn DeviceStore>,
+    auth: Arc<Auth>,
+    connections: Arc<LiveConnections>,
+}
 
 #[derive(Serialize)]
 struct StatusResponse {
     status: String,
     message: String,
+    audio: AudioSummary,
 }
 
 #[tokio::main]
@@ -35,11 +107,44 @@ async fn main() -> anyhow::Result<()> {
     info!("Используется порт: {}", port);
     info!("PORT env var: {:?}", env::var("PORT"));
 
+    let prompts = PromptStore::from_env();
+    prompts.watch();
+
+    info!("GROQ_API_KEY установлен: {}", env::var("GROQ_API_KEY").is_ok());
+
+    let chat = chat_provider_from_env(&groq_api_key())?;
+    let chat_options = ChatOptions::from_env();
+    let stores = storage::storage_from_env()?;
+    let state = AppState {
+        summarizer: Summarizer::from_env(Arc::clone(&chat), &chat_options.model).map(Arc::new),
+        chat,
+        transcriber: transcription_provider_from_env(&groq_api_key())?,
+        audio_stats: Arc::new(Mutex::new(AudioAggregate::default())),
+        hallucination_filter: Arc::new(HallucinationFilter::from_env()),
+        default_settings: Arc::new(SessionSettings {
+            chat: chat_options,
+            transcription: TranscriptionOptions::from_env(),
+        }),
+        context_budget: Arc::new(ContextBudget::from_env()),
+        prompts,
+        tts: Arc::new(OpenAiSpeechClient::from_env(&groq_api_key())),
+        sessions: Arc::new(SessionStore::from_env()),
+        storage: stores.conversations,
+        devices: stores.devices,
+        auth: Arc::new(Auth::from_env()),
+        connections: Arc::default(),
+    };
+
     let app = Router::new()
         .route("/", get(serve_index))
         .route("/api/status", get(api_status))
+        .route("/api/sessions", get(api_sessions))
+        .route("/api/sessions/{id}", get(api_session_messages))
+        .route("/api/admin/devices", get(api_devices).post(api_register_device))
+        .route("/api/admin/devices/{id}", delete(api_revoke_device))
         .route("/ws", any(websocket_handler))
         .nest_service("/static", ServeDir::new("static"))
+        .with_state(state)
         .layer(
             tower::ServiceBuilder::new()
                 .layer(CorsLayer::permissive())
@@ -84,250 +189,859 @@ async fn serve_index() -> impl IntoResponse {
         .unwrap()
 }
 
-async fn api_status() -> impl IntoResponse {
+async fn api_status(State(state): State<AppState>) -> impl IntoResponse {
     Json(StatusResponse {
         status: "ok".to_string(),
         message: "Voice Assistant Server".to_string(),
+        audio: state.audio_stats.lock().unwrap().summary(),
     })
 }
 
-async fn websocket_handler(ws: WebSocketUpgrade) -> impl IntoResponse {
-    ws.on_upgrade(handle_websocket)
+#[derive(Deserialize)]
+struct SessionsQuery {
+    device: Option<String>,
 }
 
-async fn handle_websocket(mut socket: WebSocket) {
-    info!("Клиент подключен");
-    info!("GROQ_API_KEY установлен: {}", env::var("GROQ_API_KEY").is_ok());
+type ApiError = (StatusCode, String);
 
-    let groq_api_key = env::var("GROQ_API_KEY")
-        .unwrap_or_else(|_| "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".to_string());
-    
-    let groq_client = GroqClient::new(groq_api_key);
-    let mut conversation_history: Vec<(String, String)> = Vec::new();
-    let mut is_processing = false;
-    let mut last_request_time = std::time::Instant::now();
+fn internal_error(e: anyhow::Error) -> ApiError {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// Устройствами и разговорами управляет только администратор: без `ADMIN_TOKEN` эти запросы
+/// закрыты, иначе выданные в открытом режиме токены продолжили бы действовать после включения доступа,
+/// а разговоры читал бы кто угодно.
+fn require_admin(state: &AppState, headers: &HeaderMap) -> Result<(), ApiError> {
+    if !state.auth.enabled() {
+        return Err((StatusCode::FORBIDDEN, "ADMIN_TOKEN is not set".to_string()));
+    }
+    if !state.auth.is_admin(headers) {
+        return Err((StatusCode::UNAUTHORIZED, "admin token required".to_string()));
+    }
+    Ok(())
+}
+
+/// Сохранённые разговоры, `?device=...` - одного устройства.
+async fn api_sessions(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<SessionsQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_admin(&state, &headers)?;
+    state
+        .storage
+        .sessions(query.device.as_deref())
+        .await
+        .map(Json)
+        .map_err(internal_error)
+}
+
+async fn api_session_messages(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_admin(&state, &headers)?;
+    match state.storage.messages(&id).await {
+        Ok(messages) if messages.is_empty() => Err((StatusCode::NOT_FOUND, format!("session {} not found", id))),
+        Ok(messages) => Ok(Json(messages)),
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct RegisterDevice {
+    id: String,
+}
+
+#[derive(Serialize)]
+struct IssuedToken {
+    #[serde(flatten)]
+    device: Device,
+    /// Показывается один раз, сервер хранит только хеш
+    token: String,
+}
+
+async fn api_devices(State(state): State<AppState>, headers: HeaderMap) -> Result<impl IntoResponse, ApiError> {
+    require_admin(&state, &headers)?;
+    state.devices.devices().await.map(Json).map_err(internal_error)
+}
+
+/// Регистрирует устройство или перевыпускает ему токен.
+async fn api_register_device(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<RegisterDevice>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_admin(&state, &headers)?;
+    let id = request.id.trim();
+    if id.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "device id is empty".to_string()));
+    }
+
+    let token = auth::new_token();
+    let device = state
+        .devices
+        .register_device(id, &auth::hash_token(&token))
+        .await
+        .map_err(internal_error)?;
+    info!("Выдан токен устройству {}", device.id);
+    close_connections(&state, &device.id);
+    Ok((StatusCode::CREATED, Json(IssuedToken { device, token })))
+}
+
+async fn api_revoke_device(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_admin(&state, &headers)?;
+    match state.devices.revoke_device(&id).await {
+        Ok(true) => {
+            info!("Токен устройства {} отозван", id);
+            close_connections(&state, &id);
+            Ok(StatusCode::NO_CONTENT)
+        }
+        Ok(false) => Err((StatusCode::NOT_FOUND, format!("device {} not found", id))),
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+/// Подключения со старым токеном дальше не работают.
+fn close_connections(state: &AppState, device: &str) {
+    let closed = state.connections.close(device);
+    if closed > 0 {
+        info!("Закрыто подключений устройства {}: {}", device, closed);
+    }
+}
+
+fn groq_api_key() -> String {
+    env::var("GROQ_API_KEY")
+        .unwrap_or_else(|_| "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".to_string())
+}
+
+#[derive(Deserialize)]
+struct WebSocketQuery {
+    token: Option<String>,
+}
+
+/// При включённом доступе соединение без действующего токена устройства отклоняется до апгрейда.
+async fn websocket_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<WebSocketQuery>,
+) -> Response {
+    let mut device = None;
+    let mut live = None;
+    if state.auth.enabled() {
+        let Some(token) = auth::request_token(&headers, query.token.as_deref()) else {
+            info!("Подключение без токена отклонено");
+            return (StatusCode::UNAUTHORIZED, "device token required").into_response();
+        };
+        let token_hash = auth::hash_token(token);
+        let id = match device_for_token(&state, &token_hash).await {
+            Ok(id) => id,
+            Err(response) => return response,
+        };
+        // Отзыв между проверкой и регистрацией не закрыл бы это подключение,
+        // поэтому после регистрации токен проверяется ещё раз
+        let registered = state.connections.register(&id);
+        if let Err(response) = device_for_token(&state, &token_hash).await {
+            return response;
+        }
+        live = Some(registered);
+        device = Some(id);
+    }
+    ws.on_upgrade(move |socket| handle_websocket(socket, state, device, live))
+}
+
+/// Устройство по хешу токена; `Err` - готовый ответ с отказом.
+async fn device_for_token(state: &AppState, token_hash: &str) -> Result<String, Response> {
+    match state.devices.device_by_token(token_hash).await {
+        Ok(Some(id)) => Ok(id),
+        Ok(None) => {
+            info!("Подключение с неизвестным или отозванным токеном отклонено");
+            Err((StatusCode::UNAUTHORIZED, "invalid device token").into_response())
+        }
+        Err(e) => {
+            error!("Ошибка проверки токена: {}", e);
+            Err(internal_error(e).into_response())
+        }
+    }
+}
+
+/// `device` - устройство, чей токен проверен при подключении, `live` - его запись
+/// среди открытых подключений. Без проверки доступа клиент называет себя сам сообщением `device`.
+///
+/// Команды настройки выполняются сразу при приёме, а вопросы к модели уходят
+/// в очередь сессии, поэтому `ping` и `cancel` не ждут медленного ответа.
+async fn handle_websocket(socket: WebSocket, state: AppState, device: Option<String>, live: Option<LiveConnection>) {
+    match &device {
+        Some(id) => info!("Устройство {} подключено", id),
+        None => info!("Клиент подключен"),
+    }
+
+    let authenticated = device.is_some();
+    let (mut conn, outbox) = Connection::split(socket);
+    let mut session = Session::new((*state.default_settings).clone());
+    session.device_id = device;
+    let session = Arc::new(Mutex::new(session));
+
+    let (queue, jobs) = queue::job_queue(MAX_QUEUED_JOBS);
+    let worker = tokio::spawn({
+        let state = state.clone();
+        let session = Arc::clone(&session);
+        jobs.run(move |queued| {
+            let state = state.clone();
+            let session = Arc::clone(&session);
+            async move {
+                let result = run_job(&state, &session, &queued.outbox, queued.job).await;
+                state.sessions.save(&session.lock().unwrap());
+                if let Err(e) = &result {
+                    error!("Ошибка отправки ответа: {}", e);
+                }
+                result
+            }
+        })
+    });
+
+    let mut audio = Vec::new();
+    let mut last_request_time = Instant::now();
     loop {
-        let mut all_data = Vec::new();
-        let mut recording = false;
-
-        while let Some(msg) = socket.recv().await {
-            match msg {
-                Ok(axum::extract::ws::Message::Binary(data)) => {
-                    recording = true;
-                    if let Some(pos) = data.windows(10).position(|window| window == b"END_STREAM") {
-                        all_data.extend_from_slice(&data[..pos]);
-                        break; 
+        let msg = tokio::select! {
+            msg = conn.recv() => msg,
+            _ = token_revoked(live.as_ref()) => {
+                info!("Токен устройства отозван, закрываем подключение");
+                let _ = outbox.close("device token revoked").await;
+                break;
+            }
+        };
+        let Some(msg) = msg else {
+            break;
+        };
+        let result = match msg {
+            Ok(axum::extract::ws::Message::Binary(data)) => {
+                match data.windows(10).position(|window| window == b"END_STREAM") {
+                    Some(pos) => {
+                        audio.extend_from_slice(&data[..pos]);
+                        let audio = std::mem::take(&mut audio);
+                        enqueue_voice(&queue, &outbox, audio, &mut last_request_time).await
+                    }
+                    None => {
+                        audio.extend_from_slice(&data);
+                        Ok(())
                     }
-                    all_data.extend_from_slice(&data);
                 }
-                Ok(axum::extract::ws::Message::Text(text)) => {
-                    if text == "ping" {
-                        if let Err(e) = socket.send(axum::extract::ws::Message::Text("pong".into())).await {
-                            error!("Ошибка отправки pong: {}", e);
-                            return;
-                        }
-                    } else if text == "clear_context" {
-                        conversation_history.clear();
-                        info!("Контекст разговора очищен");
-                        if let Err(e) = socket.send(axum::extract::ws::Message::Text("Контекст очищен! Начинаем новый разговор.".into())).await {
-                            error!("Ошибка отправки подтверждения: {}", e);
-                            return;
-                        }
-                    } else if text.starts_with("morse:") {
-                        let morse_code = text.strip_prefix("morse:").unwrap_or("");
-                        info!("Получен код Морзе: '{}'", morse_code);
-                        
-                        let decoded = decode_morse(morse_code);
-                        info!("Декодировано: '{}'", decoded);
-                        
-                        if decoded.is_empty() || decoded == "?" {
-                            let error_msg = format!("Не удалось декодировать: {}", morse_code);
-                            info!("{}", error_msg);
-                            if let Err(e) = socket.send(axum::extract::ws::Message::Text(error_msg.into())).await {
-                                error!("Ошибка отправки: {}", e);
-                                return;
-                            }
-                        } else {
-                            let prompt = format!(
-                                "ВАЖНО: Пользователь использует азбуку Морзе для ввода текста. \
-                                Он только что написал: \"{}\"\n\n\
-                                Это НЕ случайные буквы, это его НАСТОЯЩЕЕ сообщение, которое он хочет тебе передать. \
-                                Он потратил время, чтобы ввести это азбукой Морзе (точками и тире).\n\n\
-                                Твоя задача:\n\
-                                1. Понять смысл его сообщения: \"{}\"\n\
-                                2. Ответить на это сообщение по существу, как на обычный вопрос или фразу\n\
-                                3. НЕ повторять его сообщение\n\
-                                4. НЕ спрашивать \"чем могу помочь?\", если он задал конкретный вопрос\n\
-                                5. Отвечать содержательно и по теме\n\n\
-                                Его сообщение: \"{}\"",
-                                decoded, decoded, decoded
-                            );
-                            
-                            info!("Отправляем в AI: '{}'", prompt);
-                            
-                            match groq_client.get_chat_response_with_context(&prompt, &mut conversation_history).await {
-                                Ok(response) => {
-                                    info!("Ответ AI: '{}'", response);
-                                    conversation_history.push((decoded.clone(), response.clone()));
-                                    if conversation_history.len() > 50 {
-                                        conversation_history.remove(0);
-                                    }
-                                    
-                                    if let Err(e) = socket.send(axum::extract::ws::Message::Text(response.into())).await {
-                                        error!("Ошибка отправки ответа: {}", e);
-                                        return;
-                                    }
-                                }
-                                Err(e) => {
-                                    error!("Ошибка AI: {}", e);
-                                    let error_msg = format!("Ошибка: {}", e);
-                                    if let Err(e) = socket.send(axum::extract::ws::Message::Text(error_msg.into())).await {
-                                        error!("Ошибка отправки ошибки: {}", e);
-                                        return;
-                                    }
-                                }
-                            }
-                        }
-                    } else if text.starts_with("text:") {
-                        let now = std::time::Instant::now();
-                        if now.duration_since(last_request_time).as_secs() < 5 {
-                            let remaining = 5 - now.duration_since(last_request_time).as_secs();
-                            let error_msg = format!("Подождите {} секунд перед следующим запросом", remaining);
-                            if let Err(e) = socket.send(axum::extract::ws::Message::Text(error_msg.into())).await {
-                                error!("Ошибка отправки таймаута: {}", e);
-                                return;
+            }
+            Ok(axum::extract::ws::Message::Text(text)) => match conn.parse(&text) {
+                Ok((message, id)) => {
+                    let reply = outbox.reply_to(id);
+                    match message {
+                        ClientMessage::Text { text } => {
+                            // Смена режима - не вопрос к модели, частота на неё не ограничена
+                            let allowed = match Mode::detect(&text) {
+                                Some(_) => Ok(()),
+                                None => throttle(&mut last_request_time),
+                            };
+                            match allowed {
+                                Ok(()) => enqueue(&queue, &reply, Job::Text(text)).await,
+                                Err(error) => send_error(&reply, &error).await,
                             }
-                            continue;
                         }
-                        
-                        if is_processing {
-                            if let Err(e) = socket.send(axum::extract::ws::Message::Text("Обрабатывается предыдущий запрос, подождите...".into())).await {
-                                error!("Ошибка отправки сообщения об обработке: {}", e);
-                                return;
-                            }
-                            continue;
+                        ClientMessage::Morse { code } => enqueue(&queue, &reply, Job::Morse(code)).await,
+                        ClientMessage::AudioEnd => {
+                            let audio = std::mem::take(&mut audio);
+                            enqueue_voice(&queue, &reply, audio, &mut last_request_time).await
                         }
-                        
-                        is_processing = true;
-                        last_request_time = now;
-                        let user_text = text.strip_prefix("text:").unwrap_or(&text);
-                        info!("Получен текст: {}", user_text);
-                        
-                        match groq_client.get_chat_response_with_context(user_text, &mut conversation_history).await {
-                            Ok(response) => {
-                                conversation_history.push((user_text.to_string(), response.clone()));
-                                if conversation_history.len() > 50 {
-                                    conversation_history.remove(0);
-                                }
-                                
-                                info!("Ответ на текст: {}", response);
-                                if let Err(e) = socket.send(axum::extract::ws::Message::Text(response.into())).await {
-                                    error!("Ошибка отправки ответа на текст: {}", e);
-                                    is_processing = false;
-                                    return;
-                                }
-                            }
-                            Err(e) => {
-                                error!("Ошибка обработки текста: {}", e);
-                                let error_msg = format!("Ошибка: {}", e);
-                                if let Err(e) = socket.send(axum::extract::ws::Message::Text(error_msg.into())).await {
-                                    error!("Ошибка отправки ошибки: {}", e);
-                                    is_processing = false;
-                                    return;
-                                }
-                            }
+                        ClientMessage::Cancel => {
+                            info!("Запросы сессии отменены");
+                            queue.cancel();
+                            reply.send(ServerMessage::Status(Status::Cancelled)).await
                         }
-                        
-                        is_processing = false;
+                        message => handle_command(&state, &session, &reply, message, authenticated).await,
                     }
                 }
-                Ok(axum::extract::ws::Message::Close(_)) => {
-                    info!("Клиент отключился");
-                    return;
-                }
-                Err(e) => {
-                    error!("Ошибка WebSocket: {}", e);
-                    return;
+                Err(error) => {
+                    error!("Непонятное сообщение клиента: {}", error);
+                    send_error(&outbox, &error).await
                 }
-                _ => {}
+            },
+            Ok(axum::extract::ws::Message::Close(_)) => break,
+            Err(e) => {
+                error!("Ошибка WebSocket: {}", e);
+                break;
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            error!("Ошибка отправки: {}", e);
+            break;
+        }
+    }
+
+    // Незачем ждать ответа модели, который некому отправить
+    worker.abort();
+    info!("Клиент отключился");
+}
+
+/// Без проверки доступа подключение не закрывается никогда.
+async fn token_revoked(live: Option<&LiveConnection>) {
+    match live {
+        Some(live) => live.closed().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Не чаще одного вопроса к модели в `MIN_REQUEST_INTERVAL_SECS`.
+fn throttle(last_request_time: &mut Instant) -> Result<(), AssistantError> {
+    let elapsed = last_request_time.elapsed().as_secs();
+    if elapsed < MIN_REQUEST_INTERVAL_SECS {
+        return Err(AssistantError::RateLimited {
+            retry_after_secs: Some(MIN_REQUEST_INTERVAL_SECS - elapsed),
+        });
+    }
+    *last_request_time = Instant::now();
+    Ok(())
+}
+
+/// Ставит запрос в очередь сессии; в заполненную очередь не ставит и отвечает `busy`.
+async fn enqueue(queue: &JobQueue, reply: &Outbox, job: Job) -> Result<(), axum::Error> {
+    if queue.push(job, reply.clone()) {
+        return Ok(());
+    }
+    reply.send(ServerMessage::Status(Status::Busy)).await
+}
+
+async fn enqueue_voice(queue: &JobQueue, reply: &Outbox, audio: Vec<u8>, last_request_time: &mut Instant) -> Result<(), axum::Error> {
+    if audio.is_empty() {
+        return send_error(reply, &AssistantError::AudioFormat("empty recording".to_string())).await;
+    }
+    if let Err(error) = throttle(last_request_time) {
+        return send_error(reply, &error).await;
+    }
+    info!("Получено {} байт аудио", audio.len());
+    enqueue(queue, reply, Job::Voice(audio)).await
+}
+
+/// Команды, которые меняют настройки сессии и не ждут модель.
+async fn handle_command(
+    state: &AppState,
+    session: &Mutex<Session>,
+    reply: &Outbox,
+    message: ClientMessage,
+    authenticated: bool,
+) -> Result<(), axum::Error> {
+    match message {
+        ClientMessage::Hello { version, session: id, device } => {
+            if version != PROTOCOL_VERSION {
+                error!("Неподдерживаемая версия протокола: {}", version);
+                let error = AssistantError::Protocol(format!(
+                    "unsupported protocol version {}, server speaks {}",
+                    version, PROTOCOL_VERSION
+                ));
+                return send_error(reply, &error).await;
+            }
+            info!("Версия протокола клиента: {}", version);
+            reply.send(ServerMessage::Hello { version: PROTOCOL_VERSION }).await?;
+            // Владелец сохранённой сессии проверяется по устройству, поэтому оно задаётся раньше
+            if device.is_some() && !authenticated {
+                session.lock().unwrap().device_id = device;
             }
+            resume_session(reply, state, session, id).await?;
+        }
+        ClientMessage::Device { id } => {
+            let id = {
+                let mut session = session.lock().unwrap();
+                if authenticated {
+                    info!("Устройство называет себя {}, но его имя уже задано токеном", id);
+                } else {
+                    info!("Устройство: {}", id);
+                    session.device_id = Some(id);
+                }
+                session.device_id.clone().unwrap_or_default()
+            };
+            reply.send(ServerMessage::Status(Status::Device(id))).await?;
+        }
+        ClientMessage::Session { id } => resume_session(reply, state, session, id).await?,
+        ClientMessage::Ping => reply.send(ServerMessage::Status(Status::Pong)).await?,
+        ClientMessage::ClearContext => {
+            session.lock().unwrap().history.clear();
+            info!("Контекст разговора очищен");
+            reply.send(ServerMessage::Status(Status::ContextCleared)).await?;
         }
-        
-        if recording && !all_data.is_empty() {
-            let now = std::time::Instant::now();
-            if now.duration_since(last_request_time).as_secs() < 5 {
-                let remaining = 5 - now.duration_since(last_request_time).as_secs();
-                let error_msg = format!("Подождите {} секунд перед следующим запросом", remaining);
-                if let Err(e) = socket.send(axum::extract::ws::Message::Text(error_msg.into())).await {
-                    error!("Ошибка отправки таймаута: {}", e);
-                    return;
+        ClientMessage::Profile { profile: value } => {
+            let value = match value {
+                serde_json::Value::String(name) => name,
+                other => other.to_string(),
+            };
+            let message = match DisplayProfile::parse(&value) {
+                Some(new_profile) => {
+                    info!(
+                        "Профиль экрана: {} {}x{}, {} строк",
+                        new_profile.name, new_profile.width, new_profile.height, new_profile.max_lines
+                    );
+                    session.lock().unwrap().profile = new_profile.clone();
+                    ServerMessage::Status(Status::Profile(new_profile))
                 }
-                continue;
+                None => {
+                    error!("Неизвестный профиль экрана: {}", value);
+                    ServerMessage::from(&AssistantError::Protocol(format!("unknown display profile: {}", value)))
+                }
+            };
+            reply.send(message).await?;
+        }
+        ClientMessage::Config { reset, update } => {
+            let settings = {
+                let mut session = session.lock().unwrap();
+                if reset {
+                    session.settings = (*state.default_settings).clone();
+                } else {
+                    session.settings.apply(update);
+                }
+                session.settings.clone()
+            };
+            info!("Модель чата: {}, модель распознавания: {}", settings.chat.model, settings.transcription.model);
+            reply.send(ServerMessage::Status(Status::Config(settings))).await?;
+        }
+        ClientMessage::Stream { enabled } => {
+            session.lock().unwrap().streaming = enabled;
+            info!("Потоковые ответы: {}", enabled);
+            reply.send(ServerMessage::Status(Status::Stream(enabled))).await?;
+        }
+        ClientMessage::Pages { format } => {
+            session.lock().unwrap().page_format = format;
+            info!("Постраничные ответы: {:?}", format);
+            reply.send(ServerMessage::Status(Status::Pages(format))).await?;
+        }
+        ClientMessage::NextPage => {
+            let pager = {
+                let mut session = session.lock().unwrap();
+                session.pager.next_page();
+                session.pager.clone()
+            };
+            send_page(reply, &pager).await?;
+        }
+        ClientMessage::Mode { mode: next } => {
+            info!("Режим: {:?}", next);
+            session.lock().unwrap().mode = next;
+            reply.send(ServerMessage::Mode { mode: next }).await?;
+        }
+        ClientMessage::Tts { format } => {
+            session.lock().unwrap().speech_format = format;
+            info!("Озвучка ответов: {:?}", format);
+            reply.send(ServerMessage::Status(Status::Tts(format))).await?;
+        }
+        // Вопросы и отмену разбирает приём сообщений
+        ClientMessage::Text { .. } | ClientMessage::Morse { .. } | ClientMessage::AudioEnd | ClientMessage::Cancel => {}
+    }
+    state.sessions.save(&session.lock().unwrap());
+    Ok(())
+}
+
+/// Выполняет запрос из очереди. Ошибки модели и распознавания уходят клиенту,
+/// наружу возвращается только ошибка отправки.
+async fn run_job(state: &AppState, session: &Mutex<Session>, outbox: &Outbox, job: Job) -> Result<(), axum::Error> {
+    match job {
+        Job::Text(text) => {
+            if let Some(next) = Mode::detect(&text) {
+                return switch_mode(outbox, state, session, next).await;
             }
-            
+            info!("Получен текст: {}", text);
+            answer(outbox, state, session, Source::Text, &text, &text).await
+        }
+        Job::Morse(morse_code) => {
+            info!("Получен код Морзе: '{}'", morse_code);
 
-            if is_processing {
-                if let Err(e) = socket.send(axum::extract::ws::Message::Text("Обрабатывается предыдущий запрос, подождите...".into())).await {
-                    error!("Ошибка отправки сообщения об обработке: {}", e);
-                    return;
-                }
-                continue;
+            let decoded = decode_morse(&morse_code);
+            info!("Декодировано: '{}'", decoded);
+
+            if decoded.is_empty() || decoded == "?" {
+                info!("Не удалось декодировать: {}", morse_code);
+                return send_error(outbox, &AssistantError::Decode(morse_code)).await;
             }
-            
-            is_processing = true;
-            last_request_time = now;
-            
-            info!("Получено {} байт аудио", all_data.len());
-
-            match process_audio_with_context(&groq_client, all_data, &mut conversation_history).await {
-                Ok(response) => {
-                    info!("Ответ: {}", response);
-                    if let Err(e) = socket.send(axum::extract::ws::Message::Text(response.into())).await {
-                        error!("Ошибка отправки ответа: {}", e);
-                        is_processing = false;
-                        return;
-                    }
+            if let Some(next) = Mode::detect(&decoded) {
+                return switch_mode(outbox, state, session, next).await;
+            }
+            let training = session.lock().unwrap().mode == Mode::Training;
+            if training {
+                // В тренировке модель не нужна: показываем, что удалось расшифровать
+                let response = format!("{}\n{}", decoded, morse_code.trim());
+                return reply(outbox, state, session, &response, false, false).await;
+            }
+
+            let prompt = format!(
+                "ВАЖНО: Пользователь использует азбуку Морзе для ввода текста. \
+                Он только что написал: \"{}\"\n\n\
+                Это НЕ случайные буквы, это его НАСТОЯЩЕЕ сообщение, которое он хочет тебе передать. \
+                Он потратил время, чтобы ввести это азбукой Морзе (точками и тире).\n\n\
+                Твоя задача:\n\
+                1. Понять смысл его сообщения: \"{}\"\n\
+                2. Ответить на это сообщение по существу, как на обычный вопрос или фразу\n\
+                3. НЕ повторять его сообщение\n\
+                4. НЕ спрашивать \"чем могу помочь?\", если он задал конкретный вопрос\n\
+                5. Отвечать содержательно и по теме\n\n\
+                Его сообщение: \"{}\"",
+                decoded, decoded, decoded
+            );
+            info!("Отправляем в AI: '{}'", prompt);
+            answer(outbox, state, session, Source::Morse, &decoded, &prompt).await
+        }
+        Job::Voice(audio) => {
+            let stats = analyze_audio(&audio);
+            info!(
+                "Аудио: RMS {:.1} dBFS, пик {:.2}, клиппинг {:.3}, SNR {:.1} дБ, речь {:.1}/{:.1} с",
+                stats.rms_dbfs, stats.peak, stats.clipping_ratio, stats.snr_db, stats.speech_secs, stats.duration_secs
+            );
+            state.audio_stats.lock().unwrap().record(&stats);
+
+            match stats.verdict.user_message() {
+                Some(message) => {
+                    info!("Запись отклонена: {:?}", stats.verdict);
+                    reply(outbox, state, session, message, false, true).await?;
                 }
-                Err(e) => {
-                    error!("Ошибка обработки: {}", e);
-                    let error_msg = format!("Ошибка: {}", e);
-                    if let Err(e) = socket.send(axum::extract::ws::Message::Text(error_msg.into())).await {
-                        error!("Ошибка отправки ошибки: {}", e);
-                        is_processing = false;
-                        return;
+                None => {
+                    let settings = session.lock().unwrap().settings.clone();
+                    match transcribe_utterance(state.transcriber.as_ref(), &settings, &state.hallucination_filter, audio, &stats).await {
+                        Ok(Some(text)) => {
+                            outbox.send(ServerMessage::Transcript { text: text.clone() }).await?;
+                            match Mode::detect(&text) {
+                                Some(next) => switch_mode(outbox, state, session, next).await?,
+                                None => answer(outbox, state, session, Source::Voice, &text, &text).await?,
+                            }
+                        }
+                        Ok(None) => reply(outbox, state, session, "Не расслышал, повторите, пожалуйста", false, true).await?,
+                        Err(error) => {
+                            error!("Ошибка обработки: {}", error);
+                            send_error(outbox, &error).await?;
+                        }
                     }
                 }
             }
-            
-            is_processing = false;
-        } else if recording {
-            let _ = socket.send(axum::extract::ws::Message::Text("Нет аудио данных".to_string().into())).await;
+            outbox.send(ServerMessage::Status(Status::Stats(stats))).await
         }
     }
 }
 
-async fn process_audio_with_context(
-    groq_client: &GroqClient, 
-    audio_data: Vec<u8>, 
-    conversation_history: &mut Vec<(String, String)>
+/// Вопрос к модели с историей сессии: модели уходит `prompt`,
+/// в историю и хранилище разговоров - `question`.
+async fn answer(
+    outbox: &Outbox,
+    state: &AppState,
+    session: &Mutex<Session>,
+    source: Source,
+    question: &str,
+    prompt: &str,
+) -> Result<(), axum::Error> {
+    let (history, options, streaming, profile) = {
+        let session = session.lock().unwrap();
+        (session.history.clone(), session.settings.chat.clone(), session.streaming, session.profile.clone())
+    };
+    let system_prompt = state.prompts.get(&profile);
+    let response = match ask_assistant(outbox, state, prompt, &history, &system_prompt, &options, streaming, &profile).await {
+        Ok(response) => response,
+        Err(e) => {
+            let error = AssistantError::classify(e, AssistantError::Upstream);
+            error!("Ошибка AI: {}", error);
+            return send_error(outbox, &error).await;
+        }
+    };
+    info!("Ответ AI: '{}'", response);
+
+    let (session_id, device_id) = {
+        let mut session = session.lock().unwrap();
+        session.history.push(question.to_string(), response.clone());
+        info!("История разговора: {} сообщений", session.history.len());
+        (session.id.clone(), session.device_id.clone())
+    };
+    record_exchange(state, &session_id, device_id.as_deref(), source, question, &response).await;
+    reply(outbox, state, session, &response, streaming, true).await?;
+    compact_history(state, session, &system_prompt, &options.model).await;
+    Ok(())
+}
+
+/// Старые обмены, которые больше не влезают в бюджет, переходят в пересказ.
+async fn compact_history(state: &AppState, session: &Mutex<Session>, system_prompt: &str, model: &str) {
+    let Some(summarizer) = &state.summarizer else {
+        return;
+    };
+    let (dropped, summary) = {
+        let session = session.lock().unwrap();
+        let dropped = session.history.overflow(system_prompt, state.context_budget.for_model(model));
+        (dropped, session.history.summary().to_string())
+    };
+    if dropped.is_empty() {
+        return;
+    }
+
+    match summarizer.summarize(&summary, &dropped).await {
+        Ok(summary) => {
+            info!("Пересказано обменов: {}, конспект: {}", dropped.len(), summary);
+            if !session.lock().unwrap().history.apply_summary(&dropped, summary) {
+                info!("История изменилась во время пересказа, конспект отброшен");
+            }
+        }
+        Err(e) => error!("Не удалось пересказать историю: {}", e),
+    }
+}
+
+async fn send_error(outbox: &Outbox, error: &AssistantError) -> Result<(), axum::Error> {
+    outbox.send(ServerMessage::from(error)).await
+}
+
+/// Запрос к модели; ответ приводится к обычному тексту, в потоковом режиме куски сразу уходят клиенту.
+#[allow(clippy::too_many_arguments)]
+async fn ask_assistant(
+    outbox: &Outbox,
+    state: &AppState,
+    text: &str,
+    conversation_history: &ConversationHistory,
+    system_prompt: &str,
+    options: &ChatOptions,
+    streaming: bool,
+    profile: &DisplayProfile,
 ) -> anyhow::Result<String> {
-    let temp_file = tempfile::NamedTempFile::with_suffix(".wav")?;
+    let chat = state.chat.as_ref();
+    let budget = state.context_budget.for_model(&options.model);
+    let messages = context_messages(text, conversation_history, system_prompt, budget);
+    if !streaming {
+        return Ok(to_plain_text(&chat.chat(messages, options).await?));
+    }
+
+    let mut deltas = chat.chat_stream(messages, options).await?;
+    let mut answer = String::new();
+    let mut plain = PlainTextStream::default();
+    while let Some(delta) = deltas.next().await {
+        let delta = delta?;
+        answer.push_str(&delta);
+        if let Some(text) = plain.push(&delta) {
+            send_delta(outbox, profile.fit_font(&text)).await?;
+        }
+    }
+    if let Some(text) = plain.finish() {
+        send_delta(outbox, profile.fit_font(&text)).await?;
+    }
+
+    if answer.trim().is_empty() {
+        anyhow::bail!("No response from {}", chat.name());
+    }
+    Ok(to_plain_text(&answer))
+}
+
+async fn send_delta(outbox: &Outbox, text: String) -> Result<(), axum::Error> {
+    if text.is_empty() {
+        return Ok(());
+    }
+    outbox.send(ServerMessage::Delta { text }).await
+}
+
+/// Отправляет готовый ответ с настройками сессии: постранично, если страницы включены,
+/// и с озвучкой, если `speak` и текущий режим её допускает.
+async fn reply(
+    outbox: &Outbox,
+    state: &AppState,
+    session: &Mutex<Session>,
+    response: &str,
+    streamed: bool,
+    speak: bool,
+) -> Result<(), axum::Error> {
+    let (response, profile, pager, speech_format) = {
+        let mut session = session.lock().unwrap();
+        let response = session.profile.limit_answer(response);
+        session.pager = Pager::new(&session.profile, &response, session.page_format.unwrap_or_default());
+        let pager = session.page_format.map(|_| session.pager.clone());
+        let speech_format = session.speech_format.filter(|_| speak && session.mode.allows_speech());
+        (response, session.profile.clone(), pager, speech_format)
+    };
+    send_answer(outbox, state, &response, &profile, streamed, speech_format, pager.as_ref()).await
+}
+
+/// Отправляет готовый ответ (или первую страницу при постраничном выводе)
+/// и озвучку, если она включена. На экран идёт текст,
+/// подогнанный под шрифт устройства, в синтез речи - исходный.
+async fn send_answer(
+    outbox: &Outbox,
+    state: &AppState,
+    response: &str,
+    profile: &DisplayProfile,
+    streaming: bool,
+    speech_format: Option<SpeechFormat>,
+    pager: Option<&Pager>,
+) -> Result<(), axum::Error> {
+    if let Some(pager) = pager {
+        send_page(outbox, pager).await?;
+    } else {
+        outbox
+            .send(ServerMessage::Answer {
+                text: profile.fit_font(response),
+                streamed: streaming,
+            })
+            .await?;
+    }
+
+    if let Some(format) = speech_format {
+        send_speech(outbox, state.tts.as_ref(), response, format).await?;
+    }
+    Ok(())
+}
+
+/// Переключает режим по просьбе пользователя вместо ответа модели:
+/// подтверждение приходит обычным ответом, следом сообщение `mode`.
+async fn switch_mode(outbox: &Outbox, state: &AppState, session: &Mutex<Session>, next: Mode) -> Result<(), axum::Error> {
+    {
+        let mut session = session.lock().unwrap();
+        info!("Переключение режима: {:?} -> {:?}", session.mode, next);
+        session.mode = next;
+    }
+    reply(outbox, state, session, next.confirmation(), false, true).await?;
+    outbox.send(ServerMessage::Mode { mode: next }).await
+}
+
+/// Сессия из памяти, а после перезапуска сервера - история из хранилища разговоров.
+async fn restore_session(state: &AppState, id: &str) -> Option<Session> {
+    if let Some(session) = state.sessions.resume(id) {
+        return Some(session);
+    }
+    let messages = match state.storage.messages(id).await {
+        Ok(messages) => messages,
+        Err(e) => {
+            error!("Не удалось прочитать сессию {} из хранилища: {}", id, e);
+            return None;
+        }
+    };
+    let last = messages.last()?;
+    let mut session = Session::new((*state.default_settings).clone());
+    session.id = id.to_string();
+    session.device_id = last.device_id.clone();
+    session.history = storage::restore_history(&messages);
+    info!("Сессия {} восстановлена из хранилища", id);
+    Some(session)
+}
+
+/// Записывает обмен в хранилище разговоров; сбой хранилища ответу не мешает.
+async fn record_exchange(state: &AppState, session_id: &str, device_id: Option<&str>, source: Source, question: &str, answer: &str) {
+    let messages = storage::exchange(session_id, device_id, source, question, answer);
+    if let Err(e) = state.storage.append(messages).await {
+        error!("Ошибка записи в хранилище разговоров: {}", e);
+    }
+}
+
+/// Продолжает сохранённую сессию `id`, если она ещё жива, и сообщает клиенту идентификатор
+/// и режим сессии. Неизвестный, истёкший или чужой `id` оставляет текущую сессию.
+async fn resume_session(
+    outbox: &Outbox,
+    state: &AppState,
+    session: &Mutex<Session>,
+    id: Option<String>,
+) -> Result<(), axum::Error> {
+    let (current_id, current_device) = {
+        let session = session.lock().unwrap();
+        (session.id.clone(), session.device_id.clone())
+    };
+    let resumed = match id {
+        Some(id) if id == current_id => true,
+        // Чужую сессию не отдаём, даже если её идентификатор известен
+        Some(id) => match restore_session(state, &id)
+            .await
+            .filter(|stored| stored.belongs_to(current_device.as_deref()))
+        {
+            Some(stored) => {
+                info!("Сессия {} продолжена: {} обменов, режим {:?}", id, stored.history.len(), stored.mode);
+                let mut session = session.lock().unwrap();
+                state.sessions.remove(&session.id);
+                let device_id = session.device_id.take();
+                *session = stored;
+                session.device_id = device_id.or(session.device_id.take());
+                true
+            }
+            None => {
+                info!("Сессия {} не найдена, продолжаем {}", id, current_id);
+                false
+            }
+        },
+        None => false,
+    };
+    let (id, mode) = {
+        let session = session.lock().unwrap();
+        state.sessions.save(&session);
+        (session.id.clone(), session.mode)
+    };
+    outbox.send(ServerMessage::Session { id, resumed }).await?;
+    // Устройство после переподключения начинает с голосового режима
+    if resumed {
+        outbox.send(ServerMessage::Mode { mode }).await?;
+    }
+    Ok(())
+}
+
+/// Текущая страница ответа: строки или заголовок и картинка бинарным кадром.
+async fn send_page(outbox: &Outbox, pager: &Pager) -> Result<(), axum::Error> {
+    outbox.send(pager.message()).await?;
+    if let Some(bitmap) = pager.bitmap() {
+        outbox.send_binary(bitmap).await?;
+    }
+    Ok(())
+}
+
+/// Озвучивает ответ: заголовок, бинарные куски и `END_STREAM`.
+async fn send_speech(
+    outbox: &Outbox,
+    tts: &dyn TtsProvider,
+    text: &str,
+    format: SpeechFormat,
+) -> Result<(), axum::Error> {
+    let speech = match tts.synthesize(text, format).await {
+        Ok(speech) => speech,
+        Err(e) => {
+            error!("Ошибка синтеза речи: {}", e);
+            return Ok(());
+        }
+    };
+
+    outbox.send(ServerMessage::Speech(SpeechHeader {
+        format: speech.format,
+        sample_rate: speech.sample_rate,
+        bytes: speech.data.len(),
+    }))
+    .await?;
+
+    for chunk in speech.data.chunks(SPEECH_CHUNK_SIZE) {
+        outbox.send_binary(chunk.to_vec()).await?;
+    }
+    outbox.send_binary(b"END_STREAM".to_vec()).await
+}
+
+/// Распознаёт запись; `None`, если текст похож на галлюцинацию Whisper.
+async fn transcribe_utterance(
+    transcriber: &dyn TranscriptionProvider,
+    settings: &SessionSettings,
+    hallucination_filter: &HallucinationFilter,
+    audio_data: Vec<u8>, 
+    stats: &AudioStats,
+) -> Result<Option<String>, AssistantError> {
+    let temp_file = tempfile::NamedTempFile::with_suffix(".wav")
+        .map_err(|e| AssistantError::AudioFormat(e.to_string()))?;
     let temp_path = temp_file.path();
 
-    save_raw_as_wav(&audio_data, temp_path)?;
+    save_raw_as_wav(&audio_data, temp_path).map_err(|e| AssistantError::AudioFormat(format!("{:#}", e)))?;
 
-    let text = groq_client.transcribe_audio(temp_path).await?;
-    info!("Распознано: {}", text);
+    let transcription = transcriber
+        .transcribe(temp_path, &settings.transcription)
+        .await
+        .map_err(|e| AssistantError::classify(e, AssistantError::Transcription))?;
+    info!(
+        "Распознано [{}]: {} (avg_logprob {:.2}, no_speech_prob {:.2}, сегментов {})",
+        transcription.language.as_deref().unwrap_or("?"),
+        transcription.text,
+        transcription.avg_logprob(),
+        transcription.no_speech_prob(),
+        transcription.segments.len()
+    );
 
-    let answer = groq_client.get_chat_response_with_context(&text, conversation_history).await?;
-    
-    conversation_history.push((text.clone(), answer.clone()));
-    
-    if conversation_history.len() > 50 {
-        conversation_history.remove(0);
+    if let Some(reason) = hallucination_filter.check(&transcription, stats) {
+        info!("Распознавание отброшено как галлюцинация: {:?}", reason);
+        return Ok(None);
     }
-    
-    info!("История разговора: {} сообщений", conversation_history.len());
-    
-    Ok(answer)
+
+    Ok(Some(transcription.text.trim().to_string()))
 }
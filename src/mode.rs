@@ -0,0 +1,161 @@
+use serde::{Deserialize, Serialize};
+
+/// Режим работы устройства.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Вопросы голосом, ответы на экране и озвучкой
+    #[default]
+    Voice,
+    /// Вопросы азбукой Морзе
+    Morse,
+    /// Тренировка Морзе: сервер показывает, что расшифровал, без запроса к модели
+    Training,
+    /// Ответы только на экране, без озвучки
+    Quiet,
+}
+
+// Основы слов, по которым узнаётся режим; сравниваются с началом слова
+const MODE_WORDS: &[(&str, Mode)] = &[
+    ("голос", Mode::Voice),
+    ("обычн", Mode::Voice),
+    ("voice", Mode::Voice),
+    ("морз", Mode::Morse),
+    ("morse", Mode::Morse),
+    ("трениров", Mode::Training),
+    ("потренир", Mode::Training),
+    ("training", Mode::Training),
+    ("тих", Mode::Quiet),
+    ("тишин", Mode::Quiet),
+    ("беззвуч", Mode::Quiet),
+    ("молч", Mode::Quiet),
+    ("замолч", Mode::Quiet),
+    ("помолч", Mode::Quiet),
+    ("quiet", Mode::Quiet),
+];
+
+// Глаголы и слово "режим": без них название режима командой не считается ("Обычно", "тихо")
+const COMMAND_WORDS: &[&str] = &[
+    "перейди", "перейдем", "переходи", "переходим", "переключи", "переключись", "переключимся", "включи",
+    "давай", "хочу", "будем", "режим", "режиме", "switch", "mode",
+];
+
+// Основы, которые сами по себе просьба: "замолчи", "потренируемся"
+const COMMAND_STEMS: &[&str] = &["замолч", "помолч", "молчи", "потренир"];
+
+// Слова, которые могут окружать название режима в команде
+const FILLER_WORDS: &[&str] = &[
+    "в", "на", "во", "азбука", "азбуку", "азбуки", "азбукой", "пожалуйста", "теперь", "снова", "мне", "по", "ввод",
+    "to",
+];
+
+// Команда смены режима - короткая фраза, длинный текст считаем вопросом
+const MAX_COMMAND_WORDS: usize = 6;
+
+impl Mode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "voice" => Some(Mode::Voice),
+            "morse" => Some(Mode::Morse),
+            "training" => Some(Mode::Training),
+            "quiet" => Some(Mode::Quiet),
+            _ => None,
+        }
+    }
+
+    /// Текст, которым сервер подтверждает переключение.
+    pub fn confirmation(self) -> &'static str {
+        match self {
+            Mode::Voice => "Голосовой режим. Зажми кнопку и говори",
+            Mode::Morse => "Режим Морзе. Вводи вопрос точками и тире",
+            Mode::Training => "Тренировка Морзе. Передавай, я покажу, что понял",
+            Mode::Quiet => "Тихий режим. Отвечаю только на экране",
+        }
+    }
+
+    pub fn allows_speech(self) -> bool {
+        self != Mode::Quiet
+    }
+
+    /// Узнаёт в запросе просьбу сменить режим: "перейди на азбуку Морзе", "тихий режим",
+    /// "замолчи", "давай потренируемся". Вопросы про режимы ("что такое азбука Морзе?")
+    /// и фразы с посторонними словами командой не считаются.
+    pub fn detect(text: &str) -> Option<Self> {
+        if text.contains('?') {
+            return None;
+        }
+
+        let normalized = text.to_lowercase().replace('ё', "е");
+        let words: Vec<&str> = normalized
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .collect();
+        if words.is_empty() || words.len() > MAX_COMMAND_WORDS {
+            return None;
+        }
+
+        let mut detected = None;
+        let mut command = false;
+        for word in words {
+            if COMMAND_WORDS.contains(&word) {
+                command = true;
+                continue;
+            }
+            if FILLER_WORDS.contains(&word) {
+                continue;
+            }
+            let mode = MODE_WORDS
+                .iter()
+                .find(|(stem, _)| word.starts_with(stem))
+                .map(|(_, mode)| *mode)?;
+            if detected.is_some_and(|detected| detected != mode) {
+                return None;
+            }
+            command |= COMMAND_STEMS.iter().any(|stem| word.starts_with(stem));
+            detected = Some(mode);
+        }
+        detected.filter(|_| command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_commands() {
+        let cases = [
+            ("Перейди на азбуку Морзе", Mode::Morse),
+            ("Режим Морзе", Mode::Morse),
+            ("давай потренируемся", Mode::Training),
+            ("Потренируемся", Mode::Training),
+            ("Тихий режим, пожалуйста", Mode::Quiet),
+            ("Замолчи!", Mode::Quiet),
+            ("Включи голосовой режим", Mode::Voice),
+            ("Переключись в обычный режим", Mode::Voice),
+            ("switch to morse mode", Mode::Morse),
+        ];
+        for (text, mode) in cases {
+            assert_eq!(Mode::detect(text), Some(mode), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn ignores_questions_and_plain_words() {
+        let cases = [
+            "Обычно",
+            "Тихо",
+            "Тише",
+            "Азбука Морзе",
+            "что такое азбука Морзе?",
+            "Что такое азбука Морзе",
+            "Обычно сколько длится тренировка",
+            "Включи Морзе и голосовой режим",
+            "Перейди на азбуку Морзе и расскажи про неё подробно",
+            "",
+        ];
+        for text in cases {
+            assert_eq!(Mode::detect(text), None, "{:?}", text);
+        }
+    }
+}
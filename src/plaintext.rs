@@ -0,0 +1,641 @@
+// LaTeX и markdown в ответах модели -> обычный текст с Unicode, как просит системный промпт
+
+const SYMBOLS: &[(&str, &str)] = &[
+    ("alpha", "α"),
+    ("beta", "β"),
+    ("gamma", "γ"),
+    ("delta", "δ"),
+    ("epsilon", "ε"),
+    ("varepsilon", "ε"),
+    ("zeta", "ζ"),
+    ("eta", "η"),
+    ("theta", "θ"),
+    ("vartheta", "θ"),
+    ("iota", "ι"),
+    ("kappa", "κ"),
+    ("lambda", "λ"),
+    ("mu", "μ"),
+    ("nu", "ν"),
+    ("xi", "ξ"),
+    ("pi", "π"),
+    ("rho", "ρ"),
+    ("sigma", "σ"),
+    ("tau", "τ"),
+    ("upsilon", "υ"),
+    ("phi", "φ"),
+    ("varphi", "φ"),
+    ("chi", "χ"),
+    ("psi", "ψ"),
+    ("omega", "ω"),
+    ("Gamma", "Γ"),
+    ("Delta", "Δ"),
+    ("Theta", "Θ"),
+    ("Lambda", "Λ"),
+    ("Xi", "Ξ"),
+    ("Pi", "Π"),
+    ("Sigma", "Σ"),
+    ("Phi", "Φ"),
+    ("Psi", "Ψ"),
+    ("Omega", "Ω"),
+    ("infty", "∞"),
+    ("sum", "Σ"),
+    ("prod", "Π"),
+    ("int", "∫"),
+    ("oint", "∮"),
+    ("pm", "±"),
+    ("mp", "∓"),
+    ("times", "×"),
+    ("cdot", "·"),
+    ("div", "÷"),
+    ("le", "≤"),
+    ("leq", "≤"),
+    ("ge", "≥"),
+    ("geq", "≥"),
+    ("ne", "≠"),
+    ("neq", "≠"),
+    ("approx", "≈"),
+    ("sim", "~"),
+    ("equiv", "≡"),
+    ("propto", "∝"),
+    ("in", "∈"),
+    ("notin", "∉"),
+    ("subset", "⊂"),
+    ("supset", "⊃"),
+    ("cup", "∪"),
+    ("cap", "∩"),
+    ("emptyset", "∅"),
+    ("varnothing", "∅"),
+    ("forall", "∀"),
+    ("exists", "∃"),
+    ("nabla", "∇"),
+    ("partial", "∂"),
+    ("angle", "∠"),
+    ("perp", "⊥"),
+    ("parallel", "∥"),
+    ("circ", "°"),
+    ("degree", "°"),
+    ("to", "→"),
+    ("rightarrow", "→"),
+    ("longrightarrow", "→"),
+    ("leftarrow", "←"),
+    ("longleftarrow", "←"),
+    ("gets", "←"),
+    ("Rightarrow", "⇒"),
+    ("Longrightarrow", "⇒"),
+    ("implies", "⇒"),
+    ("Leftarrow", "⇐"),
+    ("leftrightarrow", "↔"),
+    ("Leftrightarrow", "⇔"),
+    ("iff", "⇔"),
+    ("uparrow", "↑"),
+    ("downarrow", "↓"),
+    ("rightleftharpoons", "⇌"),
+    ("ldots", "…"),
+    ("dots", "…"),
+    ("cdots", "…"),
+    ("quad", " "),
+    ("qquad", " "),
+    ("langle", "⟨"),
+    ("rangle", "⟩"),
+    ("lfloor", "⌊"),
+    ("rfloor", "⌋"),
+    ("lceil", "⌈"),
+    ("rceil", "⌉"),
+];
+
+// Команды, от которых остаётся только аргумент
+const WRAPPERS: &[&str] = &[
+    "text", "textbf", "textit", "mathrm", "mathbf", "mathit", "mathsf", "mathcal", "mathbb", "operatorname",
+    "boldsymbol", "vec", "overline", "underline", "hat", "bar", "tilde", "dot", "ddot", "overrightarrow", "boxed",
+];
+
+// Размеры скобок: `\left(` -> `(`
+const SIZES: &[&str] = &[
+    "left", "right", "big", "Big", "bigg", "Bigg", "bigl", "bigr", "Bigl", "Bigr", "biggl", "biggr",
+];
+
+// Команды-функции печатаются как есть
+const FUNCTIONS: &[&str] = &[
+    "sin", "cos", "tan", "tg", "cot", "ctg", "sec", "csc", "arcsin", "arccos", "arctan", "arctg", "sinh", "cosh",
+    "tanh", "log", "ln", "lg", "exp", "lim", "max", "min", "sup", "inf", "det", "deg", "gcd", "mod",
+];
+
+/// Приводит ответ к виду, который умеет показать экран устройства.
+pub fn to_plain_text(answer: &str) -> String {
+    let text = strip_markdown(answer, true);
+    let text = Converter::new(&text).run(None);
+    tidy(&text)
+}
+
+/// Чистит ответ по мере генерации: отдаёт текст кусками, не разрывая формулы и разметку.
+pub struct PlainTextStream {
+    pending: String,
+    // Буфер начинается с начала строки
+    line_start: bool,
+}
+
+impl Default for PlainTextStream {
+    fn default() -> Self {
+        Self {
+            pending: String::new(),
+            line_start: true,
+        }
+    }
+}
+
+impl PlainTextStream {
+    pub fn push(&mut self, delta: &str) -> Option<String> {
+        self.pending.push_str(delta);
+        let cut = safe_cut(&self.pending, self.line_start)?;
+        let ready: String = self.pending.drain(..cut).collect();
+        Some(self.convert(&ready))
+    }
+
+    pub fn finish(&mut self) -> Option<String> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let rest = std::mem::take(&mut self.pending);
+        Some(self.convert(&rest))
+    }
+
+    fn convert(&mut self, chunk: &str) -> String {
+        let text = Converter::new(&strip_markdown(chunk, self.line_start)).run(None);
+        self.line_start = chunk.ends_with('\n');
+        text
+    }
+}
+
+/// Последняя позиция после пробела, где закрыты все скобки, `$`, `*` и `` ` ``.
+/// Строки таблиц отдаём только целиком.
+fn safe_cut(text: &str, line_start: bool) -> Option<usize> {
+    let mut depth = 0i32;
+    let mut dollars = 0;
+    let mut stars = 0;
+    let mut ticks = 0;
+    let mut escaped = false;
+    let mut table_row = line_start && text.trim_start_matches(' ').starts_with('|');
+    let mut cut = None;
+
+    for (i, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        let balanced = depth <= 0 && dollars % 2 == 0 && stars % 2 == 0 && ticks % 2 == 0;
+        match c {
+            '\\' => escaped = true,
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            '$' => dollars += 1,
+            '*' => stars += 1,
+            '`' => ticks += 1,
+            '\n' if balanced => {
+                cut = Some(i + 1);
+                table_row = text[i + 1..].trim_start_matches(' ').starts_with('|');
+            }
+            c if c.is_whitespace() && balanced && !table_row => cut = Some(i + c.len_utf8()),
+            _ => {}
+        }
+    }
+    cut
+}
+
+struct Converter {
+    chars: Vec<char>,
+    pos: usize,
+    // Внутри формулы фигурные скобки - группировка, снаружи - обычный текст
+    math: bool,
+}
+
+impl Converter {
+    fn new(text: &str) -> Self {
+        Self {
+            chars: text.chars().collect(),
+            pos: 0,
+            math: false,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    /// Формула `chars[start..end]`, после неё продолжаем с `resume`.
+    fn formula(&mut self, start: usize, end: usize, resume: usize) -> String {
+        let mut inner = Converter {
+            chars: self.chars[start..end].to_vec(),
+            pos: 0,
+            math: true,
+        };
+        self.pos = resume;
+        inner.run(None)
+    }
+
+    /// Закрывающий `$` или `$$` для разделителя на `open`, как в pandoc: после
+    /// одиночного `$` не пробел, перед закрывающим не пробел и за ним не цифра,
+    /// строку одиночный не пересекает. Встреченный раньше `$` после пробела значит,
+    /// что это не формула: `$5 и $10` остаются как есть.
+    fn closing_dollar(&self, open: usize, double: bool) -> Option<usize> {
+        let start = open + if double { 2 } else { 1 };
+        if !double && self.chars.get(start).is_none_or(|c| c.is_whitespace()) {
+            return None;
+        }
+        let mut i = start;
+        while i < self.chars.len() {
+            match self.chars[i] {
+                '\\' => i += 1,
+                '\n' if !double => return None,
+                '$' if double && self.chars.get(i + 1) == Some(&'$') => return Some(i),
+                '$' if !double => {
+                    let digit_after = self.chars.get(i + 1).is_some_and(|c| c.is_ascii_digit());
+                    return (!self.chars[i - 1].is_whitespace() && !digit_after).then_some(i);
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        None
+    }
+
+    /// Закрывающая `\)` или `\]` начиная с `from`.
+    fn closing_bracket(&self, from: usize, bracket: char) -> Option<usize> {
+        (from..self.chars.len().saturating_sub(1)).find(|&i| self.chars[i] == '\\' && self.chars[i + 1] == bracket)
+    }
+
+    fn run(&mut self, until: Option<char>) -> String {
+        let mut out = String::new();
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                c if Some(c) == until => return out,
+                '\\' => out.push_str(&self.command()),
+                '{' if self.math => out.push_str(&self.run(Some('}'))),
+                '}' if self.math => {}
+                '$' if !self.math => {
+                    let open = self.pos - 1;
+                    let double = self.peek() == Some('$');
+                    let width = if double { 2 } else { 1 };
+                    match self.closing_dollar(open, double) {
+                        Some(close) => out.push_str(&self.formula(open + width, close, close + width)),
+                        None => {
+                            out.push_str(&"$".repeat(width));
+                            self.pos = open + width;
+                        }
+                    }
+                }
+                '^' if matches!(self.peek(), Some('{' | '\\')) => {
+                    let script = self.group();
+                    // 90^\circ -> 90°
+                    if script.trim() != "°" {
+                        out.push('^');
+                    }
+                    out.push_str(&wrap_script(&script));
+                }
+                '_' if self.peek() == Some('{') => {
+                    let script = self.group();
+                    out.push('_');
+                    out.push_str(&wrap_script(&script));
+                }
+                c => out.push(c),
+            }
+        }
+        out
+    }
+
+    /// Аргумент команды: `{...}`, команда или один символ.
+    fn group(&mut self) -> String {
+        while self.peek() == Some(' ') {
+            self.pos += 1;
+        }
+        match self.peek() {
+            Some('{') => {
+                self.pos += 1;
+                // Аргумент команды - уже формула
+                let math = std::mem::replace(&mut self.math, true);
+                let argument = self.run(Some('}'));
+                self.math = math;
+                argument
+            }
+            Some('\\') => {
+                self.pos += 1;
+                self.command()
+            }
+            Some(c) => {
+                self.pos += 1;
+                c.to_string()
+            }
+            None => String::new(),
+        }
+    }
+
+    fn command(&mut self) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+            self.pos += 1;
+        }
+        let name: String = self.chars[start..self.pos].iter().collect();
+
+        if name.is_empty() {
+            return match self.peek() {
+                Some(c) => {
+                    self.pos += 1;
+                    match c {
+                        '(' | '[' if !self.math => {
+                            let close = if c == '(' { ')' } else { ']' };
+                            match self.closing_bracket(self.pos, close) {
+                                Some(end) => self.formula(self.pos, end, end + 2),
+                                None => String::new(),
+                            }
+                        }
+                        '\\' => "\n".to_string(),
+                        ',' | ';' | ':' | ' ' => " ".to_string(),
+                        '!' | '(' | ')' | '[' | ']' => String::new(),
+                        c => c.to_string(),
+                    }
+                }
+                None => String::new(),
+            };
+        }
+
+        match name.as_str() {
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                let numerator = self.group();
+                let denominator = self.group();
+                format!("{}/{}", wrap_operand(&numerator), wrap_operand(&denominator))
+            }
+            "sqrt" => {
+                let index = if self.peek() == Some('[') {
+                    self.pos += 1;
+                    self.run(Some(']'))
+                } else {
+                    String::new()
+                };
+                let radicand = wrap_operand(&self.group());
+                match index.trim() {
+                    "" | "2" => format!("√{}", radicand),
+                    "3" => format!("∛{}", radicand),
+                    "4" => format!("∜{}", radicand),
+                    n => format!("{}√{}", n, radicand),
+                }
+            }
+            "begin" | "end" => {
+                self.group();
+                String::new()
+            }
+            name if SIZES.contains(&name) => {
+                // `\left.` - невидимая скобка
+                if self.peek() == Some('.') {
+                    self.pos += 1;
+                }
+                String::new()
+            }
+            name if WRAPPERS.contains(&name) => self.group(),
+            name if FUNCTIONS.contains(&name) => name.to_string(),
+            name => match SYMBOLS.iter().find(|(symbol, _)| *symbol == name) {
+                Some((_, unicode)) => {
+                    // Пробел после буквы в LaTeX - разделитель: \pi r -> πr
+                    if unicode.chars().all(char::is_alphabetic) {
+                        let mut next = self.pos;
+                        while self.chars.get(next) == Some(&' ') {
+                            next += 1;
+                        }
+                        if self.chars.get(next).is_some_and(|c| c.is_alphanumeric()) {
+                            self.pos = next;
+                        }
+                    }
+                    unicode.to_string()
+                }
+                // Вне формулы это скорее путь или экранирование, чем LaTeX: C:\Users
+                None if !self.math => format!("\\{}", name),
+                None => name.to_string(),
+            },
+        }
+    }
+}
+
+/// `x^{2}` -> `x^2`, `x^{n+1}` -> `x^(n+1)`.
+fn wrap_script(script: &str) -> String {
+    let script = script.trim();
+    if script.chars().count() == 1 || script.chars().all(|c| c.is_alphanumeric() || c == '.') {
+        script.to_string()
+    } else {
+        format!("({})", script)
+    }
+}
+
+/// Числитель, знаменатель и подкоренное выражение в скобках, если в них есть операции.
+fn wrap_operand(operand: &str) -> String {
+    let operand = operand.trim();
+    let composite = operand.chars().count() > 1
+        && operand.chars().any(|c| matches!(c, '+' | '-' | '−' | '±' | '·' | '×' | '*' | '/' | ' '));
+    if composite {
+        format!("({})", operand)
+    } else {
+        operand.to_string()
+    }
+}
+
+/// `line_start` - текст начинается с начала строки; иначе разметку строки
+/// (заголовки, списки, таблицы) в первой строке не ищем.
+fn strip_markdown(text: &str, line_start: bool) -> String {
+    let mut lines = Vec::new();
+    let mut in_code = false;
+
+    for (index, line) in text.split('\n').enumerate() {
+        if index == 0 && !line_start {
+            lines.push(strip_emphasis(&strip_inline(line)));
+            continue;
+        }
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") {
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            lines.push(line.to_string());
+            continue;
+        }
+
+        // Горизонтальные линии и разделители таблиц
+        let bare = trimmed.trim_end();
+        if bare.len() >= 3 && bare.chars().all(|c| matches!(c, '-' | '*' | '_' | '|' | ':' | ' ')) {
+            continue;
+        }
+
+        let mut line = trimmed.trim_start_matches('>').trim_start().to_string();
+        if line.starts_with('#') {
+            line = line.trim_start_matches('#').trim_start().to_string();
+        }
+        if let Some(rest) = line.strip_prefix("* ").or_else(|| line.strip_prefix("+ ")) {
+            line = format!("- {}", rest);
+        }
+        if line.starts_with('|') {
+            line = line
+                .split('|')
+                .map(str::trim)
+                .filter(|cell| !cell.is_empty())
+                .collect::<Vec<_>>()
+                .join(" | ");
+        }
+
+        lines.push(strip_emphasis(&strip_inline(&line)));
+    }
+
+    lines.join("\n")
+}
+
+fn strip_inline(line: &str) -> String {
+    strip_links(line).replace("**", "").replace("__", "").replace('`', "")
+}
+
+/// `[текст](ссылка)` -> `текст`.
+fn strip_links(line: &str) -> String {
+    let mut out = String::new();
+    let mut rest = line;
+    while let Some(open) = rest.find('[') {
+        let Some(close) = rest[open..].find("](").map(|i| open + i) else {
+            break;
+        };
+        let Some(end) = rest[close..].find(')').map(|i| close + i) else {
+            break;
+        };
+        let prefix = rest[..open].strip_suffix('!').unwrap_or(&rest[..open]);
+        out.push_str(prefix);
+        out.push_str(&rest[open + 1..close]);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// `*курсив*` -> `курсив`, но `2*3*4` не трогаем.
+fn strip_emphasis(line: &str) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let mut drop = vec![false; chars.len()];
+    let mut open: Option<usize> = None;
+
+    for (i, &c) in chars.iter().enumerate() {
+        if c != '*' {
+            continue;
+        }
+        let before = i.checked_sub(1).map(|j| chars[j]);
+        let after = chars.get(i + 1).copied();
+        match open {
+            None if after.is_some_and(char::is_alphabetic) && !before.is_some_and(char::is_alphanumeric) => {
+                open = Some(i)
+            }
+            Some(start) if before.is_some_and(|c| !c.is_whitespace()) && !after.is_some_and(char::is_alphanumeric) => {
+                drop[start] = true;
+                drop[i] = true;
+                open = None;
+            }
+            _ => {}
+        }
+    }
+
+    chars
+        .iter()
+        .zip(drop)
+        .filter(|(_, drop)| !drop)
+        .map(|(c, _)| c)
+        .collect()
+}
+
+/// Убирает лишние пробелы и пустые строки.
+fn tidy(text: &str) -> String {
+    let mut out = String::new();
+    let mut blank = 0;
+    for line in text.lines() {
+        let line = line.split(' ').filter(|word| !word.is_empty()).collect::<Vec<_>>().join(" ");
+        if line.is_empty() {
+            blank += 1;
+            continue;
+        }
+        if !out.is_empty() {
+            out.push_str(if blank > 0 { "\n\n" } else { "\n" });
+        }
+        out.push_str(&line);
+        blank = 0;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(cases: &[(&str, &str)]) {
+        for (input, expected) in cases {
+            assert_eq!(to_plain_text(input), *expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn converts_formulas() {
+        check(&[
+            (r"$\frac{1}{2}$", "1/2"),
+            (r"$\frac{a+b}{2c}$", "(a+b)/2c"),
+            (r"$x^{2}$", "x^2"),
+            (r"$x^{n+1}$", "x^(n+1)"),
+            (r"$a_{ij}$", "a_ij"),
+            (r"$90^\circ$", "90°"),
+            (r"$\sqrt{2}$", "√2"),
+            (r"$\sqrt[3]{x+1}$", "∛(x+1)"),
+            (r"\(\alpha + \beta\)", "α + β"),
+            (r"$$\pi r^2$$", "πr^2"),
+            (r"$\left( \sin x \right)$", "( sin x )"),
+        ]);
+    }
+
+    #[test]
+    fn keeps_unpaired_dollars_and_unknown_commands() {
+        check(&[
+            ("Цена $5 и $10", "Цена $5 и $10"),
+            ("Стоит 5$", "Стоит 5$"),
+            ("$ 5 и 6 $", "$ 5 и 6 $"),
+            (r"Путь C:\Users\admin", r"Путь C:\Users\admin"),
+            (r"$\foo x$", "foo x"),
+        ]);
+    }
+
+    #[test]
+    fn strips_markdown() {
+        check(&[
+            ("# Заголовок\n**жирный** и *курсив*", "Заголовок\nжирный и курсив"),
+            ("* пункт\n+ ещё", "- пункт\n- ещё"),
+            ("См. [документацию](https://example.com)", "См. документацию"),
+            ("2*3*4 = 24", "2*3*4 = 24"),
+            ("| a | b |\n|---|---|\n| 1 | 2 |", "a | b\n1 | 2"),
+            ("```\nlet x = 1;\n```", "let x = 1;"),
+            ("> цитата\n\n\n---\nдальше", "цитата\n\nдальше"),
+        ]);
+    }
+
+    #[test]
+    fn safe_cut_keeps_markup_whole() {
+        assert_eq!(safe_cut("Ответ: $x + ", true), Some("Ответ: ".len()));
+        assert_eq!(safe_cut(r"\frac{1}{2", true), None);
+        assert_eq!(safe_cut(r"дробь \frac{1}{2} и ", true), Some(r"дробь \frac{1}{2} и ".len()));
+        assert_eq!(safe_cut("так *курсив ещё", true), Some("так ".len()));
+        assert_eq!(safe_cut("код `a b", true), Some("код ".len()));
+        assert_eq!(safe_cut("| a | b", true), None);
+        assert_eq!(safe_cut("| a | b |\nтекст ", true), Some("| a | b |\nтекст ".len()));
+        assert_eq!(safe_cut("| a | b", false), Some("| a | ".len()));
+    }
+
+    #[test]
+    fn stream_matches_whole_answer() {
+        let answer = "**Ответ:** $\\frac{1}{2}$ и $x^{n+1}$\n| a | b |\n| 1 | 2 |\nготово";
+        let chars: Vec<char> = answer.chars().collect();
+        for size in [1, 2, 3, 7] {
+            let mut stream = PlainTextStream::default();
+            let mut out = String::new();
+            for piece in chars.chunks(size) {
+                out.extend(stream.push(&piece.iter().collect::<String>()));
+            }
+            out.extend(stream.finish());
+            assert_eq!(out, "Ответ: 1/2 и x^(n+1)\na | b\n1 | 2\nготово", "size {}", size);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+use tracing::{error, info, warn};
+
+use crate::display::DisplayProfile;
+
+const DEFAULT_PROMPT: &str = include_str!("../system_prompt.txt");
+const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+
+struct LoadedPrompt {
+    // None - файла нет
+    text: Option<String>,
+    modified: Option<SystemTime>,
+}
+
+/// Системные промпты из файлов, перечитываются при изменении без перезапуска.
+///
+/// Основной файл задаёт `SYSTEM_PROMPT_PATH`, варианты для экранов лежат рядом:
+/// `system_prompt.st7735.txt` для профиля `st7735`.
+pub struct PromptStore {
+    path: PathBuf,
+    // Ключ - имя профиля, "" - основной файл
+    prompts: RwLock<HashMap<String, LoadedPrompt>>,
+}
+
+impl PromptStore {
+    pub fn from_env() -> Arc<Self> {
+        let path = env::var("SYSTEM_PROMPT_PATH").unwrap_or_else(|_| "system_prompt.txt".to_string());
+        let store = Arc::new(Self {
+            path: PathBuf::from(path),
+            prompts: RwLock::new(HashMap::new()),
+        });
+
+        store.reload("");
+        if store.prompts.read().unwrap()[""].text.is_none() {
+            warn!("Файл промпта {} не найден, используем встроенный", store.path.display());
+        }
+        store
+    }
+
+    /// Промпт для профиля экрана с подставленными размерами.
+    pub fn get(&self, profile: &DisplayProfile) -> String {
+        let variant = variant_key(&profile.name);
+        // Имя профиля задаёт клиент, поэтому запоминаем только варианты, для которых есть файл
+        if !variant.is_empty()
+            && !self.prompts.read().unwrap().contains_key(&variant)
+            && self.variant_path(&variant).is_file()
+        {
+            self.reload(&variant);
+        }
+
+        let prompts = self.prompts.read().unwrap();
+        let text = prompts
+            .get(&variant)
+            .and_then(|prompt| prompt.text.as_deref())
+            .or_else(|| prompts.get("").and_then(|prompt| prompt.text.as_deref()))
+            .unwrap_or(DEFAULT_PROMPT.trim());
+
+        profile.fill_prompt(text)
+    }
+
+    /// Следит за временем изменения файлов и перечитывает их.
+    pub fn watch(self: &Arc<Self>) {
+        let store = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+            loop {
+                interval.tick().await;
+                let changed: Vec<String> = store
+                    .prompts
+                    .read()
+                    .unwrap()
+                    .iter()
+                    .filter(|(variant, loaded)| {
+                        let modified = modified_time(&store.variant_path(variant));
+                        modified != loaded.modified
+                    })
+                    .map(|(variant, _)| variant.clone())
+                    .collect();
+
+                for variant in changed {
+                    store.reload(&variant);
+                }
+            }
+        });
+    }
+
+    fn variant_path(&self, variant: &str) -> PathBuf {
+        if variant.is_empty() {
+            return self.path.clone();
+        }
+        let stem = self.path.file_stem().and_then(|s| s.to_str()).unwrap_or("system_prompt");
+        let file_name = match self.path.extension().and_then(|e| e.to_str()) {
+            Some(ext) => format!("{}.{}.{}", stem, variant, ext),
+            None => format!("{}.{}", stem, variant),
+        };
+        self.path.with_file_name(file_name)
+    }
+
+    fn reload(&self, variant: &str) {
+        let path = self.variant_path(variant);
+        let modified = modified_time(&path);
+        let mut prompts = self.prompts.write().unwrap();
+        let loaded = prompts.entry(variant.to_string()).or_insert(LoadedPrompt {
+            text: None,
+            modified: None,
+        });
+        loaded.modified = modified;
+
+        match std::fs::read_to_string(&path) {
+            Ok(content) if !content.trim().is_empty() => {
+                loaded.text = Some(content.trim().to_string());
+                info!("Системный промпт загружен из {}", path.display());
+            }
+            Ok(_) => error!("Файл промпта {} пуст, оставляем прежний", path.display()),
+            Err(_) => loaded.text = None,
+        }
+    }
+}
+
+fn variant_key(name: &str) -> String {
+    let name = name.trim().to_lowercase();
+    if name.len() <= 32 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        name
+    } else {
+        String::new()
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(dir: &Path) -> PromptStore {
+        PromptStore {
+            path: dir.join("system_prompt.txt"),
+            prompts: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn profile(name: &str) -> DisplayProfile {
+        DisplayProfile {
+            name: name.to_string(),
+            ..DisplayProfile::ssd1306()
+        }
+    }
+
+    #[test]
+    fn caches_only_existing_variants() {
+        let dir = env::temp_dir().join(format!("prompt-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("system_prompt.txt"), "Основной").unwrap();
+        std::fs::write(dir.join("system_prompt.st7735.txt"), "Для TFT").unwrap();
+        let store = store(&dir);
+        store.reload("");
+
+        assert_eq!(store.get(&profile("st7735")), "Для TFT");
+        for i in 0..100 {
+            assert_eq!(store.get(&profile(&format!("client{}", i))), "Основной");
+        }
+        let mut cached: Vec<String> = store.prompts.read().unwrap().keys().cloned().collect();
+        cached.sort();
+        assert_eq!(cached, ["", "st7735"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
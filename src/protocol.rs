@@ -0,0 +1,451 @@
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket};
+use futures_util::{
+    stream::{SplitStream, StreamExt},
+    SinkExt,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+use crate::audio::AudioStats;
+use crate::display::{BitmapPage, DisplayProfile, Page, PageFormat};
+use crate::error::AssistantError;
+use crate::mode::Mode;
+use crate::settings::{SessionSettings, SettingsUpdate};
+use crate::tts::SpeechFormat;
+
+/// Версия JSON-протокола, которую клиент называет в `hello`.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Запрос клиента в JSON-протоколе: `{"type": "text", "id": "42", "text": "..."}`.
+/// `id` необязателен и возвращается во всех ответах на этот запрос.
+#[derive(Debug, Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(flatten)]
+    message: ClientMessage,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Hello {
+        version: u32,
+        /// Идентификатор сессии, которую нужно продолжить после переподключения
+        #[serde(default)]
+        session: Option<String>,
+        /// Постоянный идентификатор устройства, под ним хранятся разговоры
+        #[serde(default)]
+        device: Option<String>,
+    },
+    /// Продолжить сессию или, без `id`, узнать идентификатор текущей
+    Session {
+        #[serde(default)]
+        id: Option<String>,
+    },
+    Device {
+        id: String,
+    },
+    Ping,
+    Text {
+        text: String,
+    },
+    Morse {
+        code: String,
+    },
+    /// Конец голосовой записи, присланной бинарными кадрами (вместо маркера `END_STREAM`)
+    AudioEnd,
+    ClearContext,
+    /// Имя профиля (`"ssd1306"`) или профиль целиком
+    Profile {
+        profile: Value,
+    },
+    Config {
+        #[serde(default)]
+        reset: bool,
+        #[serde(flatten)]
+        update: SettingsUpdate,
+    },
+    Stream {
+        enabled: bool,
+    },
+    Tts {
+        #[serde(default)]
+        format: Option<SpeechFormat>,
+    },
+    Pages {
+        #[serde(default)]
+        format: Option<PageFormat>,
+    },
+    NextPage,
+    /// Переключить режим без просьбы к модели
+    Mode {
+        mode: Mode,
+    },
+    /// Прервать текущий запрос и отбросить ожидающие в очереди
+    Cancel,
+}
+
+impl ClientMessage {
+    /// Старый формат с префиксами: `text:...`, `morse:...`, `mode:...`, `session:...`, `device:...`, `ping`, `cancel`, `clear_context`, `profile:...`, ...
+    fn parse_legacy(text: &str) -> Result<Self, AssistantError> {
+        let message = match text {
+            "ping" => ClientMessage::Ping,
+            "clear_context" => ClientMessage::ClearContext,
+            "next_page" => ClientMessage::NextPage,
+            "cancel" => ClientMessage::Cancel,
+            "session" => ClientMessage::Session { id: None },
+            "config:reset" => ClientMessage::Config {
+                reset: true,
+                update: SettingsUpdate::default(),
+            },
+            _ => {
+                let (prefix, value) = text
+                    .split_once(':')
+                    .ok_or_else(|| AssistantError::Protocol(format!("unknown command: {}", text)))?;
+                match prefix {
+                    "text" => ClientMessage::Text { text: value.to_string() },
+                    "device" => ClientMessage::Device {
+                        id: value.trim().to_string(),
+                    },
+                    "session" => ClientMessage::Session {
+                        id: Some(value.trim().to_string()).filter(|id| !id.is_empty()),
+                    },
+                    "morse" => ClientMessage::Morse { code: value.to_string() },
+                    "profile" => ClientMessage::Profile {
+                        profile: Value::String(value.to_string()),
+                    },
+                    "config" => ClientMessage::Config {
+                        reset: false,
+                        update: serde_json::from_str(value)
+                            .map_err(|e| AssistantError::Protocol(format!("invalid config: {}", e)))?,
+                    },
+                    "stream" => ClientMessage::Stream {
+                        enabled: value.trim() == "on",
+                    },
+                    "tts" => ClientMessage::Tts {
+                        format: SpeechFormat::parse(value),
+                    },
+                    "pages" => ClientMessage::Pages {
+                        format: PageFormat::parse(value),
+                    },
+                    "mode" => ClientMessage::Mode {
+                        mode: Mode::parse(value)
+                            .ok_or_else(|| AssistantError::Protocol(format!("unknown mode: {}", value)))?,
+                    },
+                    _ => return Err(AssistantError::Protocol(format!("unknown command: {}", text))),
+                }
+            }
+        };
+        Ok(message)
+    }
+}
+
+/// Ответы и уведомления сервера. В JSON-протоколе тип - поле `type`.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Hello {
+        version: u32,
+    },
+    /// Идентификатор сессии; `resumed` - продолжена сохранённая, а не начата новая
+    Session {
+        id: String,
+        resumed: bool,
+    },
+    Status(Status),
+    /// Распознанный текст голосового запроса
+    Transcript {
+        text: String,
+    },
+    /// Кусок ответа в потоковом режиме
+    Delta {
+        text: String,
+    },
+    Answer {
+        text: String,
+        /// Ответ уже пришёл кусками - в старом формате это `done:...`
+        #[serde(skip)]
+        streamed: bool,
+    },
+    /// Устройство переходит в другой режим
+    Mode {
+        mode: Mode,
+    },
+    Page(Page),
+    /// Заголовок страницы-картинки, сама картинка идёт следом бинарным кадром
+    Bitmap(BitmapPage),
+    /// Заголовок озвучки, следом бинарные кадры и `END_STREAM`
+    Speech(SpeechHeader),
+    Error(ErrorReply),
+}
+
+/// Подтверждения команд и служебные данные: `{"type":"status","status":"profile","value":{...}}`.
+#[derive(Serialize)]
+#[serde(tag = "status", content = "value", rename_all = "snake_case")]
+pub enum Status {
+    Pong,
+    ContextCleared,
+    /// Очередь запросов сессии заполнена
+    Busy,
+    /// Запросы сессии отменены
+    Cancelled,
+    Device(String),
+    Profile(DisplayProfile),
+    Config(SessionSettings),
+    Stats(AudioStats),
+    Stream(bool),
+    Tts(Option<SpeechFormat>),
+    Pages(Option<PageFormat>),
+}
+
+#[derive(Serialize)]
+pub struct SpeechHeader {
+    pub format: SpeechFormat,
+    pub sample_rate: u32,
+    pub bytes: usize,
+}
+
+#[derive(Serialize)]
+pub struct ErrorReply {
+    code: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after_secs: Option<u64>,
+}
+
+impl From<&AssistantError> for ServerMessage {
+    fn from(error: &AssistantError) -> Self {
+        let retry_after_secs = match error {
+            AssistantError::RateLimited { retry_after_secs } => *retry_after_secs,
+            _ => None,
+        };
+        ServerMessage::Error(ErrorReply {
+            code: error.code(),
+            message: error.user_message(),
+            retry_after_secs,
+        })
+    }
+}
+
+impl ServerMessage {
+    /// Сообщение в старом формате; `None` - старым клиентам не отправляется.
+    fn legacy(&self) -> Option<String> {
+        let text = match self {
+            ServerMessage::Hello { .. } | ServerMessage::Transcript { .. } => return None,
+            ServerMessage::Status(status) => match status {
+                Status::Pong => "pong".to_string(),
+                Status::ContextCleared => "Контекст очищен! Начинаем новый разговор.".to_string(),
+                Status::Busy => "Обрабатывается предыдущий запрос, подождите...".to_string(),
+                Status::Cancelled => "Запрос отменён".to_string(),
+                Status::Device(id) => format!("device:{}", id),
+                Status::Profile(profile) => format!("profile:{}", json(profile)),
+                Status::Config(settings) => format!("config:{}", json(settings)),
+                // Показатели записи нужны только JSON-клиентам
+                Status::Stats(_) => return None,
+                Status::Stream(enabled) => format!("stream:{}", if *enabled { "on" } else { "off" }),
+                Status::Tts(format) => match format {
+                    Some(SpeechFormat::Wav) => "tts:wav".to_string(),
+                    Some(SpeechFormat::Pcm) => "tts:pcm".to_string(),
+                    None => "tts:off".to_string(),
+                },
+                Status::Pages(format) => match format {
+                    Some(PageFormat::Text) => "pages:on".to_string(),
+                    Some(PageFormat::Bitmap) => "pages:bitmap".to_string(),
+                    None => "pages:off".to_string(),
+                },
+            },
+            ServerMessage::Session { id, .. } => format!("session:{}", id),
+            ServerMessage::Delta { text } => format!("delta:{}", text),
+            ServerMessage::Answer { text, streamed: true } => format!("done:{}", text),
+            ServerMessage::Answer { text, streamed: false } => text.clone(),
+            // Прошивки в поле переходят в Морзе по `/morse` в тексте и других режимов не знают
+            ServerMessage::Mode {
+                mode: Mode::Morse | Mode::Training,
+            } => "/morse".to_string(),
+            ServerMessage::Mode { .. } => return None,
+            ServerMessage::Page(page) => format!("page:{}", json(page)),
+            ServerMessage::Bitmap(page) => format!("bitmap:{}", json(page)),
+            ServerMessage::Speech(header) => format!("speech:{}", json(header)),
+            // Старые прошивки показывают текст после префикса как есть
+            ServerMessage::Error(reply) => format!("error:{}", reply.message),
+        };
+        Some(text)
+    }
+}
+
+fn json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<&'a str>,
+    #[serde(flatten)]
+    message: &'a ServerMessage,
+}
+
+// Сколько исходящих кадров может ждать отправки, пока клиент медленно читает
+const OUTBOX_CAPACITY: usize = 64;
+
+/// Приём сообщений клиента. Формат определяется по первому текстовому сообщению:
+/// JSON-объект включает JSON-протокол, иначе ответы идут в старом формате с префиксами.
+pub struct Connection {
+    stream: SplitStream<WebSocket>,
+    json: Arc<AtomicBool>,
+}
+
+impl Connection {
+    /// Делит сокет: чтение остаётся здесь, отправка уходит в отдельную задачу,
+    /// куда пишут все `Outbox`, поэтому ответы не ждут друг друга.
+    pub fn split(socket: WebSocket) -> (Self, Outbox) {
+        let (mut sink, stream) = socket.split();
+        let (tx, mut rx) = mpsc::channel::<Message>(OUTBOX_CAPACITY);
+        tokio::spawn(async move {
+            while let Some(message) = rx.recv().await {
+                if let Err(e) = sink.send(message).await {
+                    error!("Ошибка отправки клиенту: {}", e);
+                    break;
+                }
+            }
+        });
+
+        let json = Arc::new(AtomicBool::new(false));
+        let outbox = Outbox {
+            tx,
+            json: Arc::clone(&json),
+            request_id: None,
+        };
+        (Self { stream, json }, outbox)
+    }
+
+    pub async fn recv(&mut self) -> Option<Result<Message, axum::Error>> {
+        self.stream.next().await
+    }
+
+    /// Разбирает сообщение клиента; вместе с ним возвращается `id` запроса для ответов.
+    pub fn parse(&self, text: &str) -> Result<(ClientMessage, Option<String>), AssistantError> {
+        if !text.trim_start().starts_with('{') {
+            return Ok((ClientMessage::parse_legacy(text)?, None));
+        }
+
+        if !self.json.swap(true, Ordering::Relaxed) {
+            info!("Клиент перешёл на JSON-протокол");
+        }
+        let request: Request = serde_json::from_str(text)
+            .map_err(|e| AssistantError::Protocol(format!("invalid message: {}", e)))?;
+        Ok((request.message, request.id))
+    }
+}
+
+/// Отправка клиенту. Копии делят один сокет; каждая помечает ответы `id` своего запроса.
+#[derive(Clone)]
+pub struct Outbox {
+    tx: mpsc::Sender<Message>,
+    json: Arc<AtomicBool>,
+    request_id: Option<String>,
+}
+
+impl Outbox {
+    /// Копия для ответов на запрос `request_id`.
+    pub fn reply_to(&self, request_id: Option<String>) -> Self {
+        Self {
+            request_id,
+            ..self.clone()
+        }
+    }
+
+    pub async fn send(&self, message: ServerMessage) -> Result<(), axum::Error> {
+        let text = if self.json.load(Ordering::Relaxed) {
+            serde_json::to_string(&Envelope {
+                id: self.request_id.as_deref(),
+                message: &message,
+            })
+            .unwrap_or_default()
+        } else {
+            match message.legacy() {
+                Some(text) => text,
+                None => return Ok(()),
+            }
+        };
+        self.push(Message::Text(text.into())).await
+    }
+
+    pub async fn send_binary(&self, data: Vec<u8>) -> Result<(), axum::Error> {
+        self.push(Message::Binary(data.into())).await
+    }
+
+    /// Закрывает сокет с кодом нарушения политики и причиной `reason`.
+    pub async fn close(&self, reason: &str) -> Result<(), axum::Error> {
+        self.push(Message::Close(Some(CloseFrame {
+            code: close_code::POLICY,
+            reason: reason.into(),
+        })))
+        .await
+    }
+
+    async fn push(&self, message: Message) -> Result<(), axum::Error> {
+        self.tx.send(message).await.map_err(axum::Error::new)
+    }
+}
+
+#[cfg(test)]
+impl Outbox {
+    /// Отправка без сокета: кадры приходят в возвращённый канал.
+    pub fn detached() -> (Self, mpsc::Receiver<Message>) {
+        let (tx, rx) = mpsc::channel(OUTBOX_CAPACITY);
+        let outbox = Outbox {
+            tx,
+            json: Arc::new(AtomicBool::new(false)),
+            request_id: None,
+        };
+        (outbox, rx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_error_is_plain_text() {
+        let message = ServerMessage::from(&AssistantError::Transcription("whisper: 500".to_string()));
+        assert_eq!(message.legacy().as_deref(), Some("error:Не удалось распознать речь"));
+    }
+
+    #[test]
+    fn legacy_mode_uses_old_morse_command() {
+        let legacy = |mode| ServerMessage::Mode { mode }.legacy();
+        assert_eq!(legacy(Mode::Morse).as_deref(), Some("/morse"));
+        assert_eq!(legacy(Mode::Training).as_deref(), Some("/morse"));
+        assert_eq!(legacy(Mode::Voice), None);
+        assert_eq!(legacy(Mode::Quiet), None);
+    }
+
+    #[test]
+    fn json_error_keeps_code() {
+        let message = ServerMessage::from(&AssistantError::RateLimited { retry_after_secs: Some(3) });
+        let envelope = serde_json::to_value(Envelope {
+            id: Some("q1"),
+            message: &message,
+        })
+        .unwrap();
+        assert_eq!(
+            envelope,
+            serde_json::json!({
+                "id": "q1",
+                "type": "error",
+                "code": "rate_limited",
+                "message": "Подождите 3 с",
+                "retry_after_secs": 3,
+            })
+        );
+    }
+}
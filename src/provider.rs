@@ -0,0 +1,395 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures_util::{stream, Stream};
+use serde::{Deserialize, Serialize};
+use std::{env, path::Path, pin::Pin, sync::Arc};
+use tracing::info;
+
+use crate::fallback::{FallbackChain, FallbackStep};
+use crate::groq::GroqClient;
+use crate::tools::ToolCall;
+
+/// Куски ответа по мере генерации.
+pub type DeltaStream = Pin<Box<dyn Stream<Item = Result<String>> + Send>>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl ChatMessage {
+    pub fn new(role: &str, content: impl Into<String>) -> Self {
+        Self {
+            role: role.to_string(),
+            content: content.into(),
+            tool_calls: None,
+            tool_call_id: None,
+        }
+    }
+
+    /// Ход модели, в котором она вызывает инструменты; `content` - текст перед вызовами.
+    pub fn tool_calls(content: String, calls: Vec<ToolCall>) -> Self {
+        Self {
+            tool_calls: Some(calls),
+            ..Self::new("assistant", content)
+        }
+    }
+
+    pub fn tool_result(call_id: &str, content: String) -> Self {
+        Self {
+            tool_call_id: Some(call_id.to_string()),
+            ..Self::new("tool", content)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranscriptionSegment {
+    #[serde(default)]
+    pub id: u32,
+    pub start: f32,
+    pub end: f32,
+    #[serde(default)]
+    pub text: String,
+    #[serde(default)]
+    pub avg_logprob: f32,
+    #[serde(default)]
+    pub no_speech_prob: f32,
+    #[serde(default)]
+    pub compression_ratio: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transcription {
+    pub text: String,
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub duration: Option<f32>,
+    #[serde(default)]
+    pub segments: Vec<TranscriptionSegment>,
+}
+
+impl Transcription {
+    /// Средняя по длительности вероятность отсутствия речи в сегментах.
+    pub fn no_speech_prob(&self) -> f32 {
+        self.weighted(|s| s.no_speech_prob)
+            .unwrap_or_else(|| self.segments.iter().map(|s| s.no_speech_prob).fold(0.0, f32::max))
+    }
+
+    /// Средний по длительности avg_logprob: чем ближе к нулю, тем увереннее Whisper.
+    pub fn avg_logprob(&self) -> f32 {
+        self.weighted(|s| s.avg_logprob).unwrap_or(0.0)
+    }
+
+    fn weighted(&self, value: impl Fn(&TranscriptionSegment) -> f32) -> Option<f32> {
+        let total: f32 = self.segments.iter().map(|s| (s.end - s.start).max(0.0)).sum();
+        if total <= 0.0 {
+            return None;
+        }
+        let sum: f32 = self
+            .segments
+            .iter()
+            .map(|s| value(s) * (s.end - s.start).max(0.0))
+            .sum();
+        Some(sum / total)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatOptions {
+    pub model: String,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub top_p: Option<f32>,
+    /// Разрешить модели вызывать калькулятор, конвертер единиц и часы
+    pub tools: bool,
+    /// Не ждать `retry-after` при 429: в цепочке есть следующая модель
+    #[serde(skip)]
+    pub fail_fast_on_rate_limit: bool,
+}
+
+impl Default for ChatOptions {
+    fn default() -> Self {
+        Self {
+            model: "openai/gpt-oss-120b".to_string(),
+            temperature: None,
+            max_tokens: None,
+            top_p: None,
+            tools: true,
+            fail_fast_on_rate_limit: false,
+        }
+    }
+}
+
+impl ChatOptions {
+    pub fn from_env() -> Self {
+        let mut options = Self::default();
+
+        if let Ok(model) = env::var("CHAT_MODEL") {
+            if !model.trim().is_empty() {
+                options.model = model.trim().to_string();
+            }
+        }
+        options.temperature = env::var("CHAT_TEMPERATURE").ok().and_then(|v| v.parse().ok());
+        options.max_tokens = env::var("CHAT_MAX_TOKENS").ok().and_then(|v| v.parse().ok());
+        options.top_p = env::var("CHAT_TOP_P").ok().and_then(|v| v.parse().ok());
+        if let Ok(tools) = env::var("CHAT_TOOLS") {
+            options.tools = !matches!(tools.trim().to_lowercase().as_str(), "off" | "false" | "0");
+        }
+
+        options
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TranscriptionOptions {
+    pub model: String,
+    /// `None` - Whisper сам определяет язык
+    pub language: Option<String>,
+    pub prompt: Option<String>,
+    /// Термины и имена, которые Whisper должен узнавать
+    pub vocabulary: Vec<String>,
+    pub temperature: Option<f32>,
+}
+
+impl Default for TranscriptionOptions {
+    fn default() -> Self {
+        Self {
+            model: "whisper-large-v3".to_string(),
+            language: Some("ru".to_string()),
+            prompt: None,
+            vocabulary: Vec::new(),
+            temperature: None,
+        }
+    }
+}
+
+impl TranscriptionOptions {
+    pub fn from_env() -> Self {
+        let mut options = Self::default();
+
+        if let Ok(model) = env::var("WHISPER_MODEL") {
+            if !model.trim().is_empty() {
+                options.model = model.trim().to_string();
+            }
+        }
+        if let Ok(language) = env::var("WHISPER_LANGUAGE") {
+            let language = language.trim().to_lowercase();
+            options.language = match language.as_str() {
+                "" | "auto" => None,
+                _ => Some(language),
+            };
+        }
+        if let Ok(prompt) = env::var("WHISPER_PROMPT") {
+            options.prompt = Some(prompt).filter(|p| !p.trim().is_empty());
+        }
+        if let Ok(vocabulary) = env::var("WHISPER_VOCABULARY") {
+            options.vocabulary = vocabulary
+                .split(',')
+                .map(|word| word.trim().to_string())
+                .filter(|word| !word.is_empty())
+                .collect();
+        }
+        options.temperature = env::var("WHISPER_TEMPERATURE")
+            .ok()
+            .and_then(|v| v.parse().ok());
+
+        options
+    }
+
+    /// Промпт для Whisper: свободный текст плюс словарь терминов.
+    pub fn full_prompt(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        if let Some(prompt) = &self.prompt {
+            parts.push(prompt.trim().to_string());
+        }
+        if !self.vocabulary.is_empty() {
+            parts.push(format!("{}.", self.vocabulary.join(", ")));
+        }
+        if parts.is_empty() {
+            None
+        } else {
+            Some(parts.join(" "))
+        }
+    }
+}
+
+#[async_trait]
+pub trait ChatProvider: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn chat(&self, messages: Vec<ChatMessage>, options: &ChatOptions) -> Result<String>;
+
+    /// По умолчанию - весь ответ одним куском.
+    async fn chat_stream(&self, messages: Vec<ChatMessage>, options: &ChatOptions) -> Result<DeltaStream> {
+        let answer = self.chat(messages, options).await?;
+        Ok(Box::pin(stream::iter(vec![Ok(answer)])))
+    }
+}
+
+#[async_trait]
+pub trait TranscriptionProvider: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn transcribe(&self, audio_path: &Path, options: &TranscriptionOptions) -> Result<Transcription>;
+}
+
+/// Предсказуемый бэкенд без сети: для разработки и проверки без ключей.
+pub struct MockProvider {
+    transcript: String,
+}
+
+impl MockProvider {
+    pub fn new(transcript: String) -> Self {
+        Self { transcript }
+    }
+}
+
+#[async_trait]
+impl ChatProvider for MockProvider {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    async fn chat(&self, messages: Vec<ChatMessage>, _options: &ChatOptions) -> Result<String> {
+        let question = messages
+            .iter()
+            .rev()
+            .find(|message| message.role == "user")
+            .map(|message| message.content.as_str())
+            .ok_or_else(|| anyhow!("No user message"))?;
+        Ok(format!("Вы сказали: {}", question))
+    }
+
+    async fn chat_stream(&self, messages: Vec<ChatMessage>, options: &ChatOptions) -> Result<DeltaStream> {
+        let answer = self.chat(messages, options).await?;
+        let words: Vec<Result<String>> = answer
+            .split_inclusive(' ')
+            .map(|word| Ok(word.to_string()))
+            .collect();
+        Ok(Box::pin(stream::iter(words)))
+    }
+}
+
+#[async_trait]
+impl TranscriptionProvider for MockProvider {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    async fn transcribe(&self, _audio_path: &Path, _options: &TranscriptionOptions) -> Result<Transcription> {
+        Ok(Transcription {
+            text: self.transcript.clone(),
+            language: Some("ru".to_string()),
+            duration: None,
+            segments: Vec::new(),
+        })
+    }
+}
+
+/// Бэкенд по имени: `groq`, `openai` (любой совместимый `base_url`, в т.ч. Ollama и llama.cpp) или `mock`.
+fn build_client(kind: &str, base_url: Option<String>, api_key: String) -> Result<Arc<GroqClient>> {
+    match kind {
+        "groq" => Ok(Arc::new(match base_url {
+            Some(base_url) => GroqClient::openai_compatible("Groq", base_url, api_key),
+            None => GroqClient::new(api_key),
+        })),
+        "openai" => {
+            let base_url = base_url.ok_or_else(|| anyhow!("Для провайдера openai нужен *_BASE_URL"))?;
+            Ok(Arc::new(GroqClient::openai_compatible("OpenAI-compatible", base_url, api_key)))
+        }
+        _ => Err(anyhow!("Неизвестный провайдер: {}", kind)),
+    }
+}
+
+/// Бэкенд чата из `LLM_PROVIDER`, по умолчанию `groq`.
+pub fn llm_provider_kind() -> String {
+    env::var("LLM_PROVIDER").unwrap_or_else(|_| "groq".to_string()).to_lowercase()
+}
+
+/// Провайдер чата из `LLM_PROVIDER`, `LLM_BASE_URL`, `LLM_API_KEY`
+/// и запасные модели из `CHAT_FALLBACKS`.
+pub fn chat_provider_from_env(default_api_key: &str) -> Result<Arc<dyn ChatProvider>> {
+    let kind = llm_provider_kind();
+    let primary: Arc<dyn ChatProvider> = if kind == "mock" {
+        Arc::new(MockProvider::new(String::new()))
+    } else {
+        build_client(
+            &kind,
+            env::var("LLM_BASE_URL").ok(),
+            env::var("LLM_API_KEY").unwrap_or_else(|_| default_api_key.to_string()),
+        )?
+    };
+
+    let fallbacks = env::var("CHAT_FALLBACKS").unwrap_or_default();
+    let mut steps = vec![FallbackStep {
+        provider: Arc::clone(&primary),
+        model: None,
+    }];
+    for entry in fallbacks.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        steps.push(fallback_step(entry, &primary, default_api_key)?);
+    }
+
+    let provider: Arc<dyn ChatProvider> = if steps.len() == 1 {
+        primary
+    } else {
+        Arc::new(FallbackChain::new(steps))
+    };
+    info!("Провайдер чата: {}", provider.name());
+    Ok(provider)
+}
+
+/// Запасной шаг: `модель` у основного провайдера, `groq:модель`, `openai:модель`
+/// (адрес и ключ из `FALLBACK_BASE_URL`, `FALLBACK_API_KEY`) или `mock`.
+fn fallback_step(entry: &str, primary: &Arc<dyn ChatProvider>, default_api_key: &str) -> Result<FallbackStep> {
+    if entry == "mock" {
+        return Ok(FallbackStep {
+            provider: Arc::new(MockProvider::new(String::new())),
+            model: None,
+        });
+    }
+
+    // У моделей Ollama в имени тоже бывает двоеточие, поэтому префикс - только известный провайдер
+    match entry.split_once(':') {
+        Some((kind @ ("groq" | "openai"), model)) => {
+            let (base_url, api_key) = match kind {
+                "openai" => (
+                    env::var("FALLBACK_BASE_URL").ok(),
+                    env::var("FALLBACK_API_KEY").unwrap_or_default(),
+                ),
+                _ => (None, default_api_key.to_string()),
+            };
+            Ok(FallbackStep {
+                provider: build_client(kind, base_url, api_key)?,
+                model: Some(model.trim().to_string()),
+            })
+        }
+        _ => Ok(FallbackStep {
+            provider: Arc::clone(primary),
+            model: Some(entry.to_string()),
+        }),
+    }
+}
+
+/// Провайдер распознавания из `STT_PROVIDER`, `STT_BASE_URL`, `STT_API_KEY`.
+pub fn transcription_provider_from_env(default_api_key: &str) -> Result<Arc<dyn TranscriptionProvider>> {
+    let kind = env::var("STT_PROVIDER").unwrap_or_else(|_| "groq".to_string()).to_lowercase();
+    let provider: Arc<dyn TranscriptionProvider> = if kind == "mock" {
+        let transcript = env::var("MOCK_TRANSCRIPT").unwrap_or_else(|_| "Привет, как дела?".to_string());
+        Arc::new(MockProvider::new(transcript))
+    } else {
+        build_client(
+            &kind,
+            env::var("STT_BASE_URL").ok(),
+            env::var("STT_API_KEY").unwrap_or_else(|_| default_api_key.to_string()),
+        )?
+    };
+    info!("Провайдер распознавания: {}", provider.name());
+    Ok(provider)
+}
@@ -0,0 +1,186 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::{mpsc, Notify};
+use tracing::info;
+
+use crate::protocol::Outbox;
+
+/// Запрос, который ждёт модель, распознавание или синтез речи.
+pub enum Job {
+    Text(String),
+    Morse(String),
+    /// Голосовая запись целиком
+    Voice(Vec<u8>),
+}
+
+pub struct QueuedJob {
+    pub job: Job,
+    /// Ответы помечаются `id` этого запроса
+    pub outbox: Outbox,
+    generation: u64,
+}
+
+// Отмена увеличивает поколение: запросы старых поколений не выполняются,
+// а выполняющийся прерывается по уведомлению
+#[derive(Default)]
+struct Cancellation {
+    generation: AtomicU64,
+    notify: Notify,
+}
+
+/// Очередь запросов одной сессии. Запросы выполняются по одному в отдельной задаче,
+/// а приём сообщений тем временем отвечает на `ping` и команды настройки.
+pub struct JobQueue {
+    tx: mpsc::Sender<QueuedJob>,
+    cancellation: Arc<Cancellation>,
+}
+
+pub struct JobReceiver {
+    rx: mpsc::Receiver<QueuedJob>,
+    cancellation: Arc<Cancellation>,
+}
+
+/// Очередь, в которой ждут не больше `capacity` запросов сверх выполняющегося.
+pub fn job_queue(capacity: usize) -> (JobQueue, JobReceiver) {
+    let (tx, rx) = mpsc::channel(capacity);
+    let cancellation = Arc::new(Cancellation::default());
+    (
+        JobQueue {
+            tx,
+            cancellation: Arc::clone(&cancellation),
+        },
+        JobReceiver { rx, cancellation },
+    )
+}
+
+impl JobQueue {
+    /// `false`, если очередь заполнена.
+    pub fn push(&self, job: Job, outbox: Outbox) -> bool {
+        let generation = self.cancellation.generation.load(Ordering::SeqCst);
+        self.tx.try_send(QueuedJob { job, outbox, generation }).is_ok()
+    }
+
+    /// Прерывает выполняющийся запрос вместе с его обращением к модели и отбрасывает ожидающие.
+    pub fn cancel(&self) {
+        self.cancellation.generation.fetch_add(1, Ordering::SeqCst);
+        self.cancellation.notify.notify_waiters();
+    }
+}
+
+impl JobReceiver {
+    /// Выполняет запросы по очереди, пока приём сообщений не закроет очередь
+    /// или `handle` не вернёт ошибку отправки.
+    pub async fn run<F, Fut>(mut self, mut handle: F)
+    where
+        F: FnMut(QueuedJob) -> Fut,
+        Fut: Future<Output = Result<(), axum::Error>>,
+    {
+        while let Some(queued) = self.rx.recv().await {
+            // Подписка до проверки поколения, чтобы не пропустить отмену между ними
+            let cancelled = self.cancellation.notify.notified();
+            tokio::pin!(cancelled);
+            cancelled.as_mut().enable();
+            if queued.generation != self.cancellation.generation.load(Ordering::SeqCst) {
+                info!("Запрос из очереди отменён");
+                continue;
+            }
+
+            tokio::select! {
+                result = handle(queued) => {
+                    if result.is_err() {
+                        return;
+                    }
+                }
+                _ = cancelled => info!("Запрос прерван"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push(queue: &JobQueue, text: &str) -> bool {
+        queue.push(Job::Text(text.to_string()), Outbox::detached().0)
+    }
+
+    fn text(queued: &QueuedJob) -> String {
+        match &queued.job {
+            Job::Text(text) => text.clone(),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Запускает исполнителя: "wait" выполняется, пока его не прервут, остальные сразу.
+    /// В канал пишется начало каждого запроса.
+    fn spawn_worker(jobs: JobReceiver) -> (tokio::task::JoinHandle<()>, mpsc::UnboundedReceiver<String>) {
+        let (started_tx, started) = mpsc::unbounded_channel();
+        let worker = tokio::spawn(jobs.run(move |queued| {
+            let started_tx = started_tx.clone();
+            async move {
+                let text = text(&queued);
+                started_tx.send(text.clone()).unwrap();
+                if text == "wait" {
+                    std::future::pending::<()>().await;
+                }
+                Ok(())
+            }
+        }));
+        (worker, started)
+    }
+
+    #[tokio::test]
+    async fn busy_after_two_waiting_jobs() {
+        let (queue, jobs) = job_queue(2);
+        let (worker, mut started) = spawn_worker(jobs);
+
+        assert!(push(&queue, "wait"));
+        assert_eq!(started.recv().await.as_deref(), Some("wait"));
+        assert!(push(&queue, "1"));
+        assert!(push(&queue, "2"));
+        assert!(!push(&queue, "3"));
+
+        queue.cancel();
+        drop(queue);
+        worker.await.unwrap();
+        assert!(started.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn cancel_drops_waiting_jobs_and_interrupts_running() {
+        let (queue, jobs) = job_queue(2);
+        let (worker, mut started) = spawn_worker(jobs);
+
+        assert!(push(&queue, "wait"));
+        assert_eq!(started.recv().await.as_deref(), Some("wait"));
+        assert!(push(&queue, "old"));
+        queue.cancel();
+
+        // Запросы после отмены выполняются как обычно
+        assert!(push(&queue, "new"));
+        assert_eq!(started.recv().await.as_deref(), Some("new"));
+
+        drop(queue);
+        worker.await.unwrap();
+        assert!(started.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn cancel_before_run_drops_queued_jobs() {
+        let (queue, jobs) = job_queue(2);
+        assert!(push(&queue, "1"));
+        assert!(push(&queue, "2"));
+        queue.cancel();
+        drop(queue);
+
+        let (worker, mut started) = spawn_worker(jobs);
+        worker.await.unwrap();
+        assert!(started.recv().await.is_none());
+    }
+}
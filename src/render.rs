@@ -0,0 +1,166 @@
+use fontdue::{Font, FontSettings};
+use std::sync::LazyLock;
+
+use crate::display::DisplayProfile;
+
+/// Моноширинный шрифт с кириллицей, греческим и математическими символами.
+static FONT: LazyLock<Font> = LazyLock::new(|| {
+    Font::from_bytes(
+        include_bytes!("../fonts/DejaVuSansMono.ttf").as_slice(),
+        FontSettings::default(),
+    )
+    .expect("встроенный шрифт не читается")
+});
+
+// Порог яркости сглаженного глифа, с которого пиксель считается закрашенным
+const INK_THRESHOLD: u8 = 64;
+
+/// Есть ли символ во встроенном шрифте.
+pub fn has_glyph(c: char) -> bool {
+    c == '\n' || c == '\t' || FONT.has_glyph(c)
+}
+
+/// Монохромная картинка страницы в формате XBM: строки по `(width + 7) / 8` байт,
+/// младший бит - левый пиксель. Такой буфер рисует `u8g2.drawXBM`.
+pub struct Bitmap {
+    width: usize,
+    height: usize,
+    data: Vec<u8>,
+}
+
+impl Bitmap {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![0; width.div_ceil(8) * height],
+        }
+    }
+
+    fn set(&mut self, x: i32, y: i32) {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return;
+        }
+        let (x, y) = (x as usize, y as usize);
+        self.data[y * self.width.div_ceil(8) + x / 8] |= 1 << (x % 8);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Рисует строки страницы сеткой символов профиля, справа - полоску положения страницы.
+pub fn render_page(profile: &DisplayProfile, lines: &[String], index: usize, total: usize) -> Bitmap {
+    let mut bitmap = Bitmap::new(profile.width as usize, profile.height as usize);
+    let px = font_size(profile);
+    let ascent = FONT
+        .horizontal_line_metrics(px)
+        .map(|metrics| metrics.ascent)
+        .unwrap_or(px);
+    let top = ((profile.line_height as f32 - px) / 2.0).max(0.0);
+
+    for (row, line) in lines.iter().enumerate() {
+        let baseline = (row as f32 * profile.line_height as f32 + top + ascent).round() as i32;
+        for (col, c) in line.chars().enumerate() {
+            let origin = (col as u32 * profile.glyph_width) as i32;
+            draw_glyph(&mut bitmap, c, px, origin, baseline);
+        }
+    }
+
+    if total > 1 {
+        let x = profile.width as i32 - 1;
+        let travel = profile.height.saturating_sub(4) as usize;
+        let y = (travel * index / (total - 1)) as i32;
+        for dy in 0..4 {
+            bitmap.set(x, y + dy);
+        }
+    }
+
+    bitmap
+}
+
+/// Кегль, при котором символ занимает клетку `glyph_width` x `line_height`.
+fn font_size(profile: &DisplayProfile) -> f32 {
+    let advance = FONT.metrics('M', 100.0).advance_width / 100.0;
+    let height = FONT
+        .horizontal_line_metrics(100.0)
+        .map(|metrics| (metrics.ascent - metrics.descent) / 100.0)
+        .unwrap_or(1.0);
+    (profile.glyph_width as f32 / advance).min(profile.line_height as f32 / height)
+}
+
+fn draw_glyph(bitmap: &mut Bitmap, c: char, px: f32, origin: i32, baseline: i32) {
+    if c.is_whitespace() {
+        return;
+    }
+    let (metrics, coverage) = FONT.rasterize(c, px);
+    let left = origin + metrics.xmin;
+    let top = baseline - metrics.height as i32 - metrics.ymin;
+    for (i, &value) in coverage.iter().enumerate() {
+        if value >= INK_THRESHOLD {
+            let (dx, dy) = (i % metrics.width, i / metrics.width);
+            bitmap.set(left + dx as i32, top + dy as i32);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(bytes: &[u8], width: usize, x: usize, y: usize) -> bool {
+        bytes[y * width.div_ceil(8) + x / 8] & (1 << (x % 8)) != 0
+    }
+
+    #[test]
+    fn xbm_rows_are_padded_to_bytes() {
+        assert_eq!(Bitmap::new(128, 64).into_bytes().len(), 1024);
+        assert_eq!(Bitmap::new(13, 5).into_bytes().len(), 10);
+        assert_eq!(Bitmap::new(1, 1).into_bytes().len(), 1);
+    }
+
+    #[test]
+    fn least_significant_bit_is_left_pixel() {
+        let mut bitmap = Bitmap::new(13, 2);
+        bitmap.set(0, 0);
+        bitmap.set(7, 0);
+        bitmap.set(8, 1);
+        bitmap.set(12, 1);
+        // За краем ничего не рисуется
+        bitmap.set(13, 0);
+        bitmap.set(-1, 0);
+        bitmap.set(0, 2);
+        assert_eq!(bitmap.into_bytes(), [0b1000_0001, 0, 0, 0b0001_0001]);
+    }
+
+    #[test]
+    fn renders_text_inside_its_line() {
+        let profile = DisplayProfile::ssd1306();
+        let width = profile.width as usize;
+        let bytes = render_page(&profile, &["HH".to_string()], 0, 1).into_bytes();
+        assert_eq!(bytes.len(), width.div_ceil(8) * profile.height as usize);
+
+        let ink = |xs: std::ops::Range<usize>, ys: std::ops::Range<usize>| {
+            ys.flat_map(|y| xs.clone().map(move |x| (x, y))).filter(|&(x, y)| pixel(&bytes, width, x, y)).count()
+        };
+        let cell = profile.glyph_width as usize;
+        let line = profile.line_height as usize;
+        assert!(ink(0..cell, 0..line) > 0);
+        assert!(ink(cell..2 * cell, 0..line) > 0);
+        assert_eq!(ink(2 * cell..width, 0..profile.height as usize), 0);
+        assert_eq!(ink(0..width, line..profile.height as usize), 0);
+    }
+
+    #[test]
+    fn scrollbar_shows_page_position() {
+        let profile = DisplayProfile::ssd1306();
+        let (width, height) = (profile.width as usize, profile.height as usize);
+        let column = |bytes: &[u8]| (0..height).filter(|&y| pixel(bytes, width, width - 1, y)).collect::<Vec<_>>();
+
+        assert!(render_page(&profile, &[], 0, 1).into_bytes().iter().all(|&b| b == 0));
+        assert_eq!(column(&render_page(&profile, &[], 0, 3).into_bytes()), [0, 1, 2, 3]);
+        assert_eq!(column(&render_page(&profile, &[], 1, 3).into_bytes()), [30, 31, 32, 33]);
+        assert_eq!(column(&render_page(&profile, &[], 2, 3).into_bytes()), [60, 61, 62, 63]);
+    }
+}
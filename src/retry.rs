@@ -0,0 +1,283 @@
+use anyhow::Result;
+use reqwest::{header::RETRY_AFTER, RequestBuilder, Response, StatusCode};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+use crate::error::AssistantError;
+
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+const MAX_ATTEMPTS: u32 = 3;
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(8);
+// Дольше не ждём, даже если retry-after просит
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(20);
+
+const FAILURE_THRESHOLD: u32 = 5;
+const OPEN_DURATION: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    // Пробный запрос полуоткрытой цепи ещё не вернулся
+    probe_started_at: Option<Instant>,
+}
+
+/// После серии отказов перестаёт ходить в апстрим на `OPEN_DURATION`,
+/// затем пропускает один пробный запрос, остальные ждут его исхода.
+/// Пробу, которая так и не вернулась (запрос отменили), через `OPEN_DURATION` сменяет новая.
+#[derive(Default)]
+pub struct CircuitBreaker {
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn allow(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.probe_started_at.is_some_and(|started| started.elapsed() < OPEN_DURATION) {
+            return false;
+        }
+        let half_open = match state.opened_at {
+            Some(opened_at) if opened_at.elapsed() < OPEN_DURATION => return false,
+            Some(_) => true,
+            // Прежняя проба пропала, не сообщив исход
+            None => state.probe_started_at.is_some(),
+        };
+        if half_open {
+            // Следующий отказ снова откроет
+            state.opened_at = None;
+            state.probe_started_at = Some(Instant::now());
+            state.consecutive_failures = FAILURE_THRESHOLD - 1;
+        }
+        true
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures = 0;
+        state.opened_at = None;
+        state.probe_started_at = None;
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures += 1;
+        state.probe_started_at = None;
+        if state.consecutive_failures >= FAILURE_THRESHOLD && state.opened_at.is_none() {
+            warn!("Апстрим недоступен, размыкаем цепь на {} с", OPEN_DURATION.as_secs());
+            state.opened_at = Some(Instant::now());
+        }
+    }
+}
+
+/// Отправляет запрос с повторами: 5xx и сетевые ошибки - экспоненциальная
+/// задержка со случайным разбросом, 429 - столько, сколько просит `retry-after`,
+/// либо сразу ошибка, если `wait_on_rate_limit` выключен.
+pub async fn send_with_retry(
+    breaker: &CircuitBreaker,
+    wait_on_rate_limit: bool,
+    build: impl Fn() -> Result<RequestBuilder>,
+) -> Result<Response> {
+    if !breaker.allow() {
+        return Err(circuit_open());
+    }
+
+    let mut attempt = 1;
+    loop {
+        let last_attempt = attempt >= MAX_ATTEMPTS;
+
+        let delay = match build()?.send().await {
+            Ok(response) if response.status().is_success() => {
+                breaker.record_success();
+                return Ok(response);
+            }
+            Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => {
+                // Апстрим жив, хоть и просит подождать
+                breaker.record_success();
+                let retry_after = retry_after(&response);
+                warn!("Апстрим ограничил частоту запросов (попытка {}), retry-after: {:?}", attempt, retry_after);
+                let wait = wait_on_rate_limit && !last_attempt;
+                match retry_after {
+                    Some(delay) if wait && delay <= MAX_RETRY_AFTER => delay,
+                    None if wait => backoff(attempt),
+                    _ => {
+                        return Err(AssistantError::RateLimited {
+                            retry_after_secs: retry_after.map(|delay| delay.as_secs_f64().ceil() as u64),
+                        }
+                        .into())
+                    }
+                }
+            }
+            Ok(response) if response.status().is_server_error() => {
+                breaker.record_failure();
+                let status = response.status();
+                let error_text = response.text().await.unwrap_or_default();
+                warn!("Ошибка апстрима {} (попытка {}): {}", status, attempt, error_text);
+                if last_attempt {
+                    return Err(AssistantError::Upstream(format!("API error {}: {}", status, error_text)).into());
+                }
+                backoff(attempt)
+            }
+            Ok(response) => {
+                breaker.record_success();
+                let status = response.status();
+                let error_text = response.text().await.unwrap_or_default();
+                return Err(AssistantError::Upstream(format!("API error {}: {}", status, error_text)).into());
+            }
+            Err(e) => {
+                breaker.record_failure();
+                warn!("Сетевая ошибка (попытка {}): {}", attempt, e);
+                if last_attempt {
+                    return Err(AssistantError::Upstream(format!("Network error: {}", e)).into());
+                }
+                backoff(attempt)
+            }
+        };
+
+        tokio::time::sleep(delay).await;
+        if !breaker.allow() {
+            return Err(circuit_open());
+        }
+        attempt += 1;
+    }
+}
+
+fn circuit_open() -> anyhow::Error {
+    AssistantError::Upstream("circuit breaker is open".to_string()).into()
+}
+
+/// Экспоненциальная задержка с полным разбросом: от 0 до base * 2^(attempt-1).
+fn backoff(attempt: u32) -> Duration {
+    let ceiling = BASE_DELAY
+        .saturating_mul(1 << (attempt - 1).min(16))
+        .min(MAX_DELAY);
+    ceiling.mul_f64(fastrand::f64())
+}
+
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map(Duration::from_secs_f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn opened_breaker() -> CircuitBreaker {
+        let breaker = CircuitBreaker::default();
+        for _ in 0..FAILURE_THRESHOLD {
+            breaker.record_failure();
+        }
+        breaker
+    }
+
+    // Время открытия сдвигаем в прошлое, чтобы не ждать `OPEN_DURATION`
+    fn expire(breaker: &CircuitBreaker) {
+        let mut state = breaker.state.lock().unwrap();
+        let past = Instant::now() - OPEN_DURATION;
+        if state.opened_at.is_some() {
+            state.opened_at = Some(past);
+        }
+        if state.probe_started_at.is_some() {
+            state.probe_started_at = Some(past);
+        }
+    }
+
+    #[test]
+    fn opens_after_threshold() {
+        let breaker = CircuitBreaker::default();
+        for _ in 1..FAILURE_THRESHOLD {
+            breaker.record_failure();
+        }
+        assert!(breaker.allow());
+        breaker.record_failure();
+        assert!(!breaker.allow());
+    }
+
+    #[test]
+    fn half_open_lets_single_probe_through() {
+        let breaker = opened_breaker();
+        expire(&breaker);
+        assert!(breaker.allow());
+        assert!(!breaker.allow());
+        assert!(!breaker.allow());
+
+        breaker.record_success();
+        assert!(breaker.allow());
+        assert!(breaker.allow());
+    }
+
+    #[test]
+    fn failed_probe_reopens() {
+        let breaker = opened_breaker();
+        expire(&breaker);
+        assert!(breaker.allow());
+        breaker.record_failure();
+        assert!(!breaker.allow());
+    }
+
+    /// Сервер, который на каждый запрос отвечает 429 с `retry-after: 15`; возвращает адрес и счётчик запросов.
+    async fn rate_limited_server() -> (String, Arc<AtomicUsize>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut buffer = [0; 4096];
+                let _ = socket.read(&mut buffer).await;
+                let _ = socket
+                    .write_all(b"HTTP/1.1 429 Too Many Requests\r\nretry-after: 15\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                    .await;
+            }
+        });
+        (address, requests)
+    }
+
+    #[tokio::test]
+    async fn rate_limit_fails_fast_when_asked() {
+        let (address, requests) = rate_limited_server().await;
+        let client = reqwest::Client::new();
+        let breaker = CircuitBreaker::default();
+
+        let started = Instant::now();
+        let error = send_with_retry(&breaker, false, || Ok(client.get(&address)))
+            .await
+            .unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert!(matches!(
+            error.downcast_ref::<AssistantError>(),
+            Some(AssistantError::RateLimited { retry_after_secs: Some(15) })
+        ));
+        // Отказ по лимиту не размыкает цепь
+        assert!(breaker.allow());
+    }
+
+    #[test]
+    fn lost_probe_is_replaced() {
+        let breaker = opened_breaker();
+        expire(&breaker);
+        assert!(breaker.allow());
+        assert!(!breaker.allow());
+        expire(&breaker);
+        assert!(breaker.allow());
+        assert!(!breaker.allow());
+    }
+}
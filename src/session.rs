@@ -0,0 +1,176 @@
+use std::{
+    collections::HashMap,
+    env,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::context::ConversationHistory;
+use crate::display::{DisplayProfile, PageFormat, Pager};
+use crate::mode::Mode;
+use crate::settings::SessionSettings;
+use crate::tts::SpeechFormat;
+
+const DEFAULT_TTL_SECS: u64 = 30 * 60;
+
+/// Состояние разговора, которое переживает переподключение устройства.
+#[derive(Clone)]
+pub struct Session {
+    pub id: String,
+    pub device_id: Option<String>,
+    pub history: ConversationHistory,
+    pub settings: SessionSettings,
+    pub mode: Mode,
+    pub profile: DisplayProfile,
+    pub speech_format: Option<SpeechFormat>,
+    pub streaming: bool,
+    pub page_format: Option<PageFormat>,
+    pub pager: Pager,
+}
+
+impl Session {
+    /// Новая сессия со свежим идентификатором. В хранилище она попадает при первом `save`.
+    pub fn new(settings: SessionSettings) -> Self {
+        Self {
+            id: Uuid::new_v4().simple().to_string(),
+            device_id: None,
+            history: ConversationHistory::default(),
+            settings,
+            mode: Mode::default(),
+            profile: DisplayProfile::default(),
+            speech_format: None,
+            streaming: false,
+            page_format: None,
+            pager: Pager::default(),
+        }
+    }
+
+    /// Можно ли отдать сессию подключению устройства `device`: сессию устройства - только ему же.
+    pub fn belongs_to(&self, device: Option<&str>) -> bool {
+        self.device_id.is_none() || self.device_id.as_deref() == device
+    }
+}
+
+struct StoredSession {
+    session: Session,
+    last_seen: Instant,
+}
+
+/// Сессии всех устройств по идентификатору. Сессия, к которой долго
+/// не обращались (`SESSION_TTL_SECS`, по умолчанию 30 минут), удаляется.
+pub struct SessionStore {
+    sessions: Mutex<HashMap<String, StoredSession>>,
+    ttl: Duration,
+}
+
+impl SessionStore {
+    pub fn from_env() -> Self {
+        let ttl = env::var("SESSION_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_TTL_SECS);
+        info!("Сессии хранятся {} с без активности", ttl);
+        Self::with_ttl(Duration::from_secs(ttl))
+    }
+
+    fn with_ttl(ttl: Duration) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            ttl,
+        }
+    }
+
+    /// Сохранённое состояние сессии, если она ещё не истекла.
+    pub fn resume(&self, id: &str) -> Option<Session> {
+        let mut sessions = self.sessions.lock().unwrap();
+        self.purge(&mut sessions);
+        let stored = sessions.get_mut(id)?;
+        stored.last_seen = Instant::now();
+        Some(stored.session.clone())
+    }
+
+    pub fn save(&self, session: &Session) {
+        let mut sessions = self.sessions.lock().unwrap();
+        self.purge(&mut sessions);
+        sessions.insert(
+            session.id.clone(),
+            StoredSession {
+                session: session.clone(),
+                last_seen: Instant::now(),
+            },
+        );
+    }
+
+    pub fn remove(&self, id: &str) {
+        self.sessions.lock().unwrap().remove(id);
+    }
+
+    fn purge(&self, sessions: &mut HashMap<String, StoredSession>) {
+        let before = sessions.len();
+        sessions.retain(|_, stored| stored.last_seen.elapsed() < self.ttl);
+        if sessions.len() < before {
+            info!("Удалено истёкших сессий: {}", before - sessions.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::{ChatOptions, TranscriptionOptions};
+    use std::thread::sleep;
+
+    fn session(device: Option<&str>) -> Session {
+        let mut session = Session::new(SessionSettings {
+            chat: ChatOptions::from_env(),
+            transcription: TranscriptionOptions::from_env(),
+        });
+        session.device_id = device.map(str::to_string);
+        session
+    }
+
+    #[test]
+    fn resumes_saved_session() {
+        let store = SessionStore::with_ttl(Duration::from_secs(60));
+        let mut saved = session(None);
+        saved.mode = Mode::Morse;
+        store.save(&saved);
+
+        let resumed = store.resume(&saved.id).unwrap();
+        assert_eq!(resumed.mode, Mode::Morse);
+        assert!(store.resume("unknown").is_none());
+
+        store.remove(&saved.id);
+        assert!(store.resume(&saved.id).is_none());
+    }
+
+    #[test]
+    fn expires_after_ttl_without_activity() {
+        let store = SessionStore::with_ttl(Duration::from_millis(100));
+        let saved = session(None);
+        store.save(&saved);
+
+        // Каждое обращение продлевает сессию
+        sleep(Duration::from_millis(60));
+        assert!(store.resume(&saved.id).is_some());
+        sleep(Duration::from_millis(60));
+        assert!(store.resume(&saved.id).is_some());
+
+        sleep(Duration::from_millis(150));
+        assert!(store.resume(&saved.id).is_none());
+    }
+
+    #[test]
+    fn device_session_is_resumed_only_by_the_same_device() {
+        let anonymous = session(None);
+        assert!(anonymous.belongs_to(None));
+        assert!(anonymous.belongs_to(Some("kitchen")));
+
+        let kitchen = session(Some("kitchen"));
+        assert!(kitchen.belongs_to(Some("kitchen")));
+        assert!(!kitchen.belongs_to(Some("hall")));
+        assert!(!kitchen.belongs_to(None));
+    }
+}
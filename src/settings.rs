@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+use crate::provider::{ChatOptions, TranscriptionOptions};
+
+/// Модели и параметры генерации, которые сессия может переопределить.
+#[derive(Clone, Serialize)]
+pub struct SessionSettings {
+    pub chat: ChatOptions,
+    pub transcription: TranscriptionOptions,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SettingsUpdate {
+    chat_model: Option<String>,
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+    top_p: Option<f32>,
+    transcription_model: Option<String>,
+    language: Option<String>,
+}
+
+impl SessionSettings {
+    pub fn apply(&mut self, update: SettingsUpdate) {
+        if let Some(model) = update.chat_model {
+            self.chat.model = model;
+        }
+        if update.temperature.is_some() {
+            self.chat.temperature = update.temperature;
+        }
+        if update.max_tokens.is_some() {
+            self.chat.max_tokens = update.max_tokens;
+        }
+        if update.top_p.is_some() {
+            self.chat.top_p = update.top_p;
+        }
+        if let Some(model) = update.transcription_model {
+            self.transcription.model = model;
+        }
+        if let Some(language) = update.language {
+            self.transcription.language = match language.as_str() {
+                "" | "auto" => None,
+                _ => Some(language),
+            };
+        }
+    }
+}
@@ -0,0 +1,561 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    env,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::info;
+
+use crate::context::ConversationHistory;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+/// Как пользователь задал вопрос.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Text,
+    /// Текст - распознанная запись
+    Voice,
+    /// Текст - расшифрованный код
+    Morse,
+}
+
+impl Role {
+    fn as_str(self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "user" => Some(Role::User),
+            "assistant" => Some(Role::Assistant),
+            _ => None,
+        }
+    }
+}
+
+impl Source {
+    fn as_str(self) -> &'static str {
+        match self {
+            Source::Text => "text",
+            Source::Voice => "voice",
+            Source::Morse => "morse",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "text" => Some(Source::Text),
+            "voice" => Some(Source::Voice),
+            "morse" => Some(Source::Morse),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredMessage {
+    pub session_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+    pub role: Role,
+    /// Только у вопросов пользователя
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<Source>,
+    pub text: String,
+    /// Unix-время в секундах
+    pub created_at: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionSummary {
+    pub session_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+    pub messages: usize,
+    pub first_at: u64,
+    pub last_at: u64,
+}
+
+/// Долговременное хранилище разговоров: переживает перезапуск сервера.
+#[async_trait]
+pub trait ConversationStore: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn append(&self, messages: Vec<StoredMessage>) -> Result<()>;
+
+    /// Сообщения сессии от старых к новым.
+    async fn messages(&self, session_id: &str) -> Result<Vec<StoredMessage>>;
+
+    /// Сессии от недавних к старым, с `device_id` - только этого устройства.
+    async fn sessions(&self, device_id: Option<&str>) -> Result<Vec<SessionSummary>>;
+}
+
+/// Зарегистрированное устройство. Сам токен не хранится, только его хеш.
+#[derive(Debug, Clone, Serialize)]
+pub struct Device {
+    pub id: String,
+    pub created_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<u64>,
+}
+
+/// Устройства и хеши их токенов доступа.
+#[async_trait]
+pub trait DeviceStore: Send + Sync {
+    /// Регистрирует устройство или выдаёт ему новый токен: прежний перестаёт действовать.
+    async fn register_device(&self, id: &str, token_hash: &str) -> Result<Device>;
+
+    /// Идентификатор устройства по хешу токена, если токен не отозван.
+    async fn device_by_token(&self, token_hash: &str) -> Result<Option<String>>;
+
+    /// `false`, если такого устройства нет.
+    async fn revoke_device(&self, id: &str) -> Result<bool>;
+
+    async fn devices(&self) -> Result<Vec<Device>>;
+}
+
+/// Разговоры и устройства в одном бэкенде.
+pub struct Stores {
+    pub conversations: Arc<dyn ConversationStore>,
+    pub devices: Arc<dyn DeviceStore>,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Вопрос и ответ одного обмена для записи в хранилище.
+pub fn exchange(
+    session_id: &str,
+    device_id: Option<&str>,
+    source: Source,
+    question: &str,
+    answer: &str,
+) -> Vec<StoredMessage> {
+    let created_at = unix_now();
+    let message = |role, source, text: &str| StoredMessage {
+        session_id: session_id.to_string(),
+        device_id: device_id.map(str::to_string),
+        role,
+        source,
+        text: text.to_string(),
+        created_at,
+    };
+    vec![
+        message(Role::User, Some(source), question),
+        message(Role::Assistant, None, answer),
+    ]
+}
+
+/// История разговора из сохранённых сообщений: вопросы, за которыми идёт ответ.
+pub fn restore_history(messages: &[StoredMessage]) -> ConversationHistory {
+    let mut history = ConversationHistory::default();
+    for pair in messages.windows(2) {
+        if pair[0].role == Role::User && pair[1].role == Role::Assistant {
+            history.push(pair[0].text.clone(), pair[1].text.clone());
+        }
+    }
+    history
+}
+
+// Сколько последних сообщений всех сессий держит хранилище в памяти
+const MEMORY_STORE_LIMIT: usize = 10_000;
+
+/// Хранилище в памяти процесса: для разработки и когда диск не нужен.
+/// Хранит последние `MEMORY_STORE_LIMIT` сообщений, более старые вытесняются.
+pub struct MemoryStore {
+    messages: Mutex<VecDeque<StoredMessage>>,
+    devices: Mutex<HashMap<String, (Device, String)>>,
+    limit: usize,
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::with_limit(MEMORY_STORE_LIMIT)
+    }
+}
+
+impl MemoryStore {
+    fn with_limit(limit: usize) -> Self {
+        Self {
+            messages: Mutex::new(VecDeque::new()),
+            devices: Mutex::new(HashMap::new()),
+            limit,
+        }
+    }
+}
+
+#[async_trait]
+impl ConversationStore for MemoryStore {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    async fn append(&self, messages: Vec<StoredMessage>) -> Result<()> {
+        let mut stored = self.messages.lock().unwrap();
+        stored.extend(messages);
+        let excess = stored.len().saturating_sub(self.limit);
+        stored.drain(..excess);
+        Ok(())
+    }
+
+    async fn messages(&self, session_id: &str) -> Result<Vec<StoredMessage>> {
+        Ok(self
+            .messages
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|message| message.session_id == session_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn sessions(&self, device_id: Option<&str>) -> Result<Vec<SessionSummary>> {
+        let messages = self.messages.lock().unwrap();
+        let mut sessions: HashMap<&str, SessionSummary> = HashMap::new();
+        for message in messages.iter() {
+            if device_id.is_some() && message.device_id.as_deref() != device_id {
+                continue;
+            }
+            let summary = sessions.entry(&message.session_id).or_insert_with(|| SessionSummary {
+                session_id: message.session_id.clone(),
+                device_id: None,
+                messages: 0,
+                first_at: message.created_at,
+                last_at: message.created_at,
+            });
+            summary.device_id = summary.device_id.take().or_else(|| message.device_id.clone());
+            summary.messages += 1;
+            summary.first_at = summary.first_at.min(message.created_at);
+            summary.last_at = summary.last_at.max(message.created_at);
+        }
+
+        let mut sessions: Vec<SessionSummary> = sessions.into_values().collect();
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.last_at));
+        Ok(sessions)
+    }
+}
+
+#[async_trait]
+impl DeviceStore for MemoryStore {
+    async fn register_device(&self, id: &str, token_hash: &str) -> Result<Device> {
+        let device = Device {
+            id: id.to_string(),
+            created_at: unix_now(),
+            revoked_at: None,
+        };
+        self.devices
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), (device.clone(), token_hash.to_string()));
+        Ok(device)
+    }
+
+    async fn device_by_token(&self, token_hash: &str) -> Result<Option<String>> {
+        Ok(self
+            .devices
+            .lock()
+            .unwrap()
+            .values()
+            .find(|(device, hash)| hash == token_hash && device.revoked_at.is_none())
+            .map(|(device, _)| device.id.clone()))
+    }
+
+    async fn revoke_device(&self, id: &str) -> Result<bool> {
+        let mut devices = self.devices.lock().unwrap();
+        let Some((device, _)) = devices.get_mut(id) else {
+            return Ok(false);
+        };
+        device.revoked_at.get_or_insert_with(unix_now);
+        Ok(true)
+    }
+
+    async fn devices(&self) -> Result<Vec<Device>> {
+        let mut devices: Vec<Device> = self.devices.lock().unwrap().values().map(|(device, _)| device.clone()).collect();
+        devices.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(devices)
+    }
+}
+
+/// Встроенная SQLite-база в одном файле.
+pub struct SqliteStore {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    pub fn open(path: &str) -> Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS messages (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                session_id TEXT NOT NULL,
+                device_id TEXT,
+                role TEXT NOT NULL,
+                source TEXT,
+                text TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS messages_session ON messages (session_id, id);
+            CREATE INDEX IF NOT EXISTS messages_device ON messages (device_id);
+            CREATE TABLE IF NOT EXISTS devices (
+                id TEXT PRIMARY KEY,
+                token_hash TEXT NOT NULL UNIQUE,
+                created_at INTEGER NOT NULL,
+                revoked_at INTEGER
+            );",
+        )?;
+
+        let count: i64 = connection.query_row("SELECT COUNT(*) FROM messages", [], |row| row.get(0))?;
+        info!("База разговоров {}: {} сообщений", path, count);
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// rusqlite синхронный, поэтому запросы идут в пуле блокирующих задач.
+    async fn run<T: Send + 'static>(&self, query: impl FnOnce(&mut Connection) -> Result<T> + Send + 'static) -> Result<T> {
+        let connection = Arc::clone(&self.connection);
+        tokio::task::spawn_blocking(move || query(&mut connection.lock().unwrap())).await?
+    }
+}
+
+#[async_trait]
+impl ConversationStore for SqliteStore {
+    fn name(&self) -> &str {
+        "sqlite"
+    }
+
+    async fn append(&self, messages: Vec<StoredMessage>) -> Result<()> {
+        self.run(move |connection| {
+            let transaction = connection.transaction()?;
+            for message in &messages {
+                transaction.execute(
+                    "INSERT INTO messages (session_id, device_id, role, source, text, created_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![
+                        message.session_id,
+                        message.device_id,
+                        message.role.as_str(),
+                        message.source.map(Source::as_str),
+                        message.text,
+                        message.created_at as i64,
+                    ],
+                )?;
+            }
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn messages(&self, session_id: &str) -> Result<Vec<StoredMessage>> {
+        let session_id = session_id.to_string();
+        self.run(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT session_id, device_id, role, source, text, created_at
+                 FROM messages WHERE session_id = ?1 ORDER BY id",
+            )?;
+            let rows = statement.query_map(params![session_id], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, i64>(5)?,
+                ))
+            })?;
+
+            let mut messages = Vec::new();
+            for row in rows {
+                let (session_id, device_id, role, source, text, created_at) = row?;
+                messages.push(StoredMessage {
+                    session_id,
+                    device_id,
+                    role: Role::parse(&role).ok_or_else(|| anyhow!("Неизвестная роль в базе: {}", role))?,
+                    source: source.as_deref().and_then(Source::parse),
+                    text,
+                    created_at: created_at as u64,
+                });
+            }
+            Ok(messages)
+        })
+        .await
+    }
+
+    async fn sessions(&self, device_id: Option<&str>) -> Result<Vec<SessionSummary>> {
+        let device_id = device_id.map(str::to_string);
+        self.run(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT session_id, MAX(device_id), COUNT(*), MIN(created_at), MAX(created_at)
+                 FROM messages WHERE ?1 IS NULL OR device_id = ?1
+                 GROUP BY session_id ORDER BY MAX(created_at) DESC",
+            )?;
+            let sessions = statement
+                .query_map(params![device_id], |row| {
+                    Ok(SessionSummary {
+                        session_id: row.get(0)?,
+                        device_id: row.get(1)?,
+                        messages: row.get::<_, i64>(2)? as usize,
+                        first_at: row.get::<_, i64>(3)? as u64,
+                        last_at: row.get::<_, i64>(4)? as u64,
+                    })
+                })?
+                .collect::<Result<_, _>>()?;
+            Ok(sessions)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl DeviceStore for SqliteStore {
+    async fn register_device(&self, id: &str, token_hash: &str) -> Result<Device> {
+        let device = Device {
+            id: id.to_string(),
+            created_at: unix_now(),
+            revoked_at: None,
+        };
+        let (id, token_hash, created_at) = (device.id.clone(), token_hash.to_string(), device.created_at as i64);
+        self.run(move |connection| {
+            connection.execute(
+                "INSERT INTO devices (id, token_hash, created_at) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (id) DO UPDATE SET
+                     token_hash = excluded.token_hash, created_at = excluded.created_at, revoked_at = NULL",
+                params![id, token_hash, created_at],
+            )?;
+            Ok(())
+        })
+        .await?;
+        Ok(device)
+    }
+
+    async fn device_by_token(&self, token_hash: &str) -> Result<Option<String>> {
+        let token_hash = token_hash.to_string();
+        self.run(move |connection| {
+            let id = connection
+                .query_row(
+                    "SELECT id FROM devices WHERE token_hash = ?1 AND revoked_at IS NULL",
+                    params![token_hash],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            Ok(id)
+        })
+        .await
+    }
+
+    async fn revoke_device(&self, id: &str) -> Result<bool> {
+        let id = id.to_string();
+        self.run(move |connection| {
+            let changed = connection.execute(
+                "UPDATE devices SET revoked_at = COALESCE(revoked_at, ?2) WHERE id = ?1",
+                params![id, unix_now() as i64],
+            )?;
+            Ok(changed > 0)
+        })
+        .await
+    }
+
+    async fn devices(&self) -> Result<Vec<Device>> {
+        self.run(|connection| {
+            let mut statement = connection.prepare("SELECT id, created_at, revoked_at FROM devices ORDER BY id")?;
+            let devices = statement
+                .query_map([], |row| {
+                    Ok(Device {
+                        id: row.get(0)?,
+                        created_at: row.get::<_, i64>(1)? as u64,
+                        revoked_at: row.get::<_, Option<i64>>(2)?.map(|at| at as u64),
+                    })
+                })?
+                .collect::<Result<_, _>>()?;
+            Ok(devices)
+        })
+        .await
+    }
+}
+
+/// `STORAGE=memory` (по умолчанию) или `STORAGE=sqlite` с файлом `DATABASE_PATH`.
+pub fn storage_from_env() -> Result<Stores> {
+    let kind = env::var("STORAGE").unwrap_or_else(|_| "memory".to_string()).to_lowercase();
+    let stores = match kind.as_str() {
+        "memory" => {
+            let store = Arc::new(MemoryStore::default());
+            Stores {
+                conversations: store.clone(),
+                devices: store,
+            }
+        }
+        "sqlite" => {
+            let path = env::var("DATABASE_PATH").unwrap_or_else(|_| "conversations.db".to_string());
+            let store = Arc::new(SqliteStore::open(&path)?);
+            Stores {
+                conversations: store.clone(),
+                devices: store,
+            }
+        }
+        _ => return Err(anyhow!("Неизвестное хранилище: {}", kind)),
+    };
+    info!("Хранилище разговоров: {}", stores.conversations.name());
+    Ok(stores)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(session_id: &str, text: &str) -> StoredMessage {
+        StoredMessage {
+            session_id: session_id.to_string(),
+            device_id: None,
+            role: Role::User,
+            source: Some(Source::Text),
+            text: text.to_string(),
+            created_at: unix_now(),
+        }
+    }
+
+    fn texts(messages: Vec<StoredMessage>) -> Vec<String> {
+        messages.into_iter().map(|message| message.text).collect()
+    }
+
+    #[tokio::test]
+    async fn memory_store_drops_oldest_messages() {
+        let store = MemoryStore::with_limit(3);
+        store.append(vec![message("a", "1"), message("a", "2")]).await.unwrap();
+        store.append(vec![message("b", "3"), message("a", "4")]).await.unwrap();
+        assert_eq!(texts(store.messages("a").await.unwrap()), ["2", "4"]);
+
+        store.append(vec![message("b", "5"), message("b", "6"), message("b", "7")]).await.unwrap();
+        assert!(store.messages("a").await.unwrap().is_empty());
+        assert_eq!(texts(store.messages("b").await.unwrap()), ["5", "6", "7"]);
+
+        let sessions = store.sessions(None).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].messages, 3);
+    }
+
+    #[tokio::test]
+    async fn memory_store_keeps_batch_larger_than_limit_tail() {
+        let store = MemoryStore::with_limit(2);
+        store.append(vec![message("a", "1"), message("a", "2"), message("a", "3")]).await.unwrap();
+        assert_eq!(texts(store.messages("a").await.unwrap()), ["2", "3"]);
+    }
+}
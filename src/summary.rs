@@ -0,0 +1,65 @@
+use anyhow::Result;
+use std::{env, sync::Arc};
+use tracing::info;
+
+use crate::fallback::FALLBACK_NOTE;
+use crate::provider::{llm_provider_kind, ChatMessage, ChatOptions, ChatProvider};
+
+const SUMMARY_PROMPT: &str = "Ты ведёшь конспект разговора ученика с ИИ-ассистентом. \
+Обнови конспект: добавь к нему новые реплики. Сохрани темы, факты, имена, числа, \
+формулы и договорённости, отбрось приветствия и повторы. \
+Пиши по-русски, сжато, не больше 8 предложений. Выведи только конспект.";
+
+/// Пересказывает выпавшие из окна контекста обмены дешёвой моделью.
+pub struct Summarizer {
+    chat: Arc<dyn ChatProvider>,
+    options: ChatOptions,
+}
+
+impl Summarizer {
+    /// Модель из `SUMMARY_MODEL`, `off` - отключить пересказ. По умолчанию у Groq - дешёвая
+    /// `llama-3.1-8b-instant`, у остальных бэкендов её нет, и пересказывает модель чата.
+    pub fn from_env(chat: Arc<dyn ChatProvider>, chat_model: &str) -> Option<Self> {
+        let model = env::var("SUMMARY_MODEL").unwrap_or_else(|_| match llm_provider_kind().as_str() {
+            "groq" => "llama-3.1-8b-instant".to_string(),
+            _ => chat_model.to_string(),
+        });
+        let model = model.trim();
+        if model.is_empty() || model == "off" {
+            info!("Пересказ истории отключён");
+            return None;
+        }
+
+        info!("Модель пересказа истории: {}", model);
+        Some(Self {
+            chat,
+            options: ChatOptions {
+                model: model.to_string(),
+                temperature: Some(0.2),
+                max_tokens: Some(300),
+                top_p: None,
+                tools: false,
+                fail_fast_on_rate_limit: false,
+            },
+        })
+    }
+
+    /// Новый конспект: прежний плюс выпавшие обмены.
+    pub async fn summarize(&self, summary: &str, dropped: &[(String, String)]) -> Result<String> {
+        let mut transcript = String::new();
+        if !summary.is_empty() {
+            transcript.push_str(&format!("Конспект до этого: {}\n\n", summary));
+        }
+        transcript.push_str("Новые реплики:\n");
+        for (question, answer) in dropped {
+            transcript.push_str(&format!("Ученик: {}\nАссистент: {}\n", question, answer));
+        }
+
+        let messages = vec![
+            ChatMessage::new("system", SUMMARY_PROMPT),
+            ChatMessage::new("user", transcript),
+        ];
+        let summary = self.chat.chat(messages, &self.options).await?;
+        Ok(summary.trim_start_matches(FALLBACK_NOTE).trim().to_string())
+    }
+}
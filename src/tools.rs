@@ -0,0 +1,173 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    env,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{info, warn};
+
+use crate::calc;
+use crate::units;
+
+// Москва по умолчанию
+const DEFAULT_UTC_OFFSET_HOURS: i64 = 3;
+const WEEKDAYS: [&str; 7] = [
+    "понедельник",
+    "вторник",
+    "среда",
+    "четверг",
+    "пятница",
+    "суббота",
+    "воскресенье",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionCall {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub arguments: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type", default = "function_type")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+fn function_type() -> String {
+    "function".to_string()
+}
+
+/// Описания инструментов для `tools` в запросе к модели.
+pub fn definitions() -> Value {
+    json!([
+        {
+            "type": "function",
+            "function": {
+                "name": "calculate",
+                "description": "Точно вычисляет арифметическое выражение. Используй для любых вычислений вместо счёта в уме. \
+                    Поддерживает + - * / ^ !, скобки, sqrt, sin, cos, tan, asin, acos, atan (радианы), ln, log (десятичный), exp, abs, pi, e.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "expression": { "type": "string", "description": "Выражение, например (3/4 + 2^10) * sqrt(2)" }
+                    },
+                    "required": ["expression"]
+                }
+            }
+        },
+        {
+            "type": "function",
+            "function": {
+                "name": "convert_units",
+                "description": "Переводит величину из одних единиц в другие: длина, масса, время, скорость, площадь, объём, \
+                    энергия, мощность, давление, сила, угол, информация, температура (°C, °F, K).",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "value": { "type": "number" },
+                        "from": { "type": "string", "description": "Исходная единица, например km/h или км/ч; регистр приставок важен: mW и MW, Mb и MB" },
+                        "to": { "type": "string", "description": "Целевая единица, например m/s" }
+                    },
+                    "required": ["value", "from", "to"]
+                }
+            }
+        },
+        {
+            "type": "function",
+            "function": {
+                "name": "current_datetime",
+                "description": "Текущие дата, время и день недели.",
+                "parameters": { "type": "object", "properties": {} }
+            }
+        }
+    ])
+}
+
+#[derive(Deserialize)]
+struct CalculateArgs {
+    expression: String,
+}
+
+#[derive(Deserialize)]
+struct ConvertArgs {
+    value: f64,
+    from: String,
+    to: String,
+}
+
+/// Выполняет вызов инструмента. Ошибка возвращается текстом, чтобы модель могла исправиться.
+pub fn run(call: &ToolCall) -> String {
+    let result = match call.function.name.as_str() {
+        "calculate" => parse_args::<CalculateArgs>(&call.function.arguments)
+            .and_then(|args| Ok(format!("{} = {}", args.expression, calc::evaluate(&args.expression)?))),
+        "convert_units" => parse_args::<ConvertArgs>(&call.function.arguments)
+            .and_then(|args| units::convert(args.value, &args.from, &args.to)),
+        "current_datetime" => Ok(current_datetime()),
+        name => Err(anyhow!("неизвестный инструмент {}", name)),
+    };
+
+    match result {
+        Ok(output) => {
+            info!("Инструмент {}: {}", call.function.name, output);
+            output
+        }
+        Err(e) => {
+            warn!("Инструмент {} ({}): {}", call.function.name, call.function.arguments, e);
+            format!("Ошибка: {}", e)
+        }
+    }
+}
+
+fn parse_args<T: for<'de> Deserialize<'de>>(arguments: &str) -> Result<T> {
+    let arguments = if arguments.trim().is_empty() { "{}" } else { arguments };
+    serde_json::from_str(arguments).map_err(|e| anyhow!("неверные аргументы: {}", e))
+}
+
+/// Время в поясе `UTC_OFFSET_HOURS` (по умолчанию UTC+3).
+fn current_datetime() -> String {
+    let offset_hours = env::var("UTC_OFFSET_HOURS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_UTC_OFFSET_HOURS);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+        + offset_hours * 3600;
+
+    let days = now.div_euclid(86400);
+    let seconds = now.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    // 1970-01-01 - четверг
+    let weekday = WEEKDAYS[(days + 3).rem_euclid(7) as usize];
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}, {} (UTC{:+})",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        weekday,
+        offset_hours
+    )
+}
+
+/// Дни от 1970-01-01 в дату григорианского календаря (алгоритм Х. Хиннанта).
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
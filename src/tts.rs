@@ -0,0 +1,158 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::{env, io::Cursor};
+
+use crate::retry::{CONNECT_TIMEOUT, READ_TIMEOUT};
+
+// Лимит OpenAI-совместимых speech-эндпоинтов
+const MAX_INPUT_CHARS: usize = 4000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpeechFormat {
+    Wav,
+    Pcm,
+}
+
+impl SpeechFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "wav" => Some(SpeechFormat::Wav),
+            "pcm" => Some(SpeechFormat::Pcm),
+            _ => None,
+        }
+    }
+}
+
+pub struct Speech {
+    pub format: SpeechFormat,
+    pub sample_rate: u32,
+    pub data: Vec<u8>,
+}
+
+#[async_trait]
+pub trait TtsProvider: Send + Sync {
+    async fn synthesize(&self, text: &str, format: SpeechFormat) -> Result<Speech>;
+}
+
+#[derive(Serialize)]
+struct SpeechRequest<'a> {
+    model: &'a str,
+    voice: &'a str,
+    input: &'a str,
+    response_format: &'a str,
+}
+
+/// Клиент для `/audio/speech` в стиле OpenAI (Groq, OpenAI и совместимые).
+pub struct OpenAiSpeechClient {
+    client: Client,
+    base_url: String,
+    api_key: String,
+    model: String,
+    voice: String,
+}
+
+impl OpenAiSpeechClient {
+    pub fn new(base_url: String, api_key: String, model: String, voice: String) -> Self {
+        let client = Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .read_timeout(READ_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+
+        Self {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+            model,
+            voice,
+        }
+    }
+
+    pub fn from_env(default_api_key: &str) -> Self {
+        Self::new(
+            env::var("TTS_BASE_URL").unwrap_or_else(|_| "https://api.groq.com/openai/v1".to_string()),
+            env::var("TTS_API_KEY").unwrap_or_else(|_| default_api_key.to_string()),
+            env::var("TTS_MODEL").unwrap_or_else(|_| "playai-tts".to_string()),
+            env::var("TTS_VOICE").unwrap_or_else(|_| "Fritz-PlayAI".to_string()),
+        )
+    }
+}
+
+#[async_trait]
+impl TtsProvider for OpenAiSpeechClient {
+    async fn synthesize(&self, text: &str, format: SpeechFormat) -> Result<Speech> {
+        let input: String = text.chars().take(MAX_INPUT_CHARS).collect();
+        let request = SpeechRequest {
+            model: &self.model,
+            voice: &self.voice,
+            input: &input,
+            response_format: "wav",
+        };
+
+        let response = self
+            .client
+            .post(format!("{}/audio/speech", self.base_url))
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .json(&request)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await?;
+            return Err(anyhow!("TTS API error: {}", error_text));
+        }
+
+        let wav = response.bytes().await?.to_vec();
+        let sample_rate = hound::WavReader::new(Cursor::new(&wav))?.spec().sample_rate;
+
+        let data = match format {
+            SpeechFormat::Wav => wav,
+            SpeechFormat::Pcm => wav_to_pcm(&wav)?,
+        };
+
+        Ok(Speech {
+            format,
+            sample_rate,
+            data,
+        })
+    }
+}
+
+/// Достаёт из WAV моно PCM 16 бит little-endian.
+fn wav_to_pcm(wav: &[u8]) -> Result<Vec<u8>> {
+    let mut reader = hound::WavReader::new(Cursor::new(wav))?;
+    let spec = reader.spec();
+    let channels = spec.channels.max(1) as usize;
+
+    let samples: Vec<i16> = match spec.sample_format {
+        hound::SampleFormat::Int => {
+            let shift = spec.bits_per_sample as i32 - 16;
+            reader
+                .samples::<i32>()
+                .map(|s| {
+                    s.map(|v| {
+                        if shift >= 0 {
+                            (v >> shift) as i16
+                        } else {
+                            (v << -shift) as i16
+                        }
+                    })
+                })
+                .collect::<Result<_, _>>()?
+        }
+        hound::SampleFormat::Float => reader
+            .samples::<f32>()
+            .map(|s| s.map(|v| (v.clamp(-1.0, 1.0) * i16::MAX as f32) as i16))
+            .collect::<Result<_, _>>()?,
+    };
+
+    let mut pcm = Vec::with_capacity(samples.len() / channels * 2);
+    for frame in samples.chunks(channels) {
+        let mixed = frame.iter().map(|&s| s as i32).sum::<i32>() / frame.len() as i32;
+        pcm.extend_from_slice(&(mixed as i16).to_le_bytes());
+    }
+    Ok(pcm)
+}
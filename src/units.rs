@@ -0,0 +1,240 @@
+use anyhow::{bail, Result};
+
+use crate::calc::format_float;
+
+/// Единица: варианты написания, величина и множитель к единице СИ.
+/// Написания с заглавными буквами сравниваются с учётом регистра (mW и MW, Mb и MB), остальные - без.
+struct Unit {
+    names: &'static [&'static str],
+    quantity: &'static str,
+    factor: f64,
+}
+
+const fn unit(names: &'static [&'static str], quantity: &'static str, factor: f64) -> Unit {
+    Unit {
+        names,
+        quantity,
+        factor,
+    }
+}
+
+const UNITS: &[Unit] = &[
+    unit(&["m", "м", "meter", "метр"], "длина", 1.0),
+    unit(&["km", "км"], "длина", 1e3),
+    unit(&["dm", "дм"], "длина", 1e-1),
+    unit(&["cm", "см"], "длина", 1e-2),
+    unit(&["mm", "мм"], "длина", 1e-3),
+    unit(&["um", "µm", "мкм"], "длина", 1e-6),
+    unit(&["nm", "нм"], "длина", 1e-9),
+    unit(&["mi", "mile", "миля"], "длина", 1609.344),
+    unit(&["yd", "ярд"], "длина", 0.9144),
+    unit(&["ft", "фут"], "длина", 0.3048),
+    unit(&["in", "inch", "дюйм"], "длина", 0.0254),
+    unit(&["nmi", "морская миля"], "длина", 1852.0),
+    unit(&["au", "а.е."], "длина", 1.495978707e11),
+    unit(&["ly", "св.год", "световой год"], "длина", 9.4607304725808e15),
+    unit(&["kg", "кг"], "масса", 1.0),
+    unit(&["g", "г"], "масса", 1e-3),
+    unit(&["mg", "мг"], "масса", 1e-6),
+    unit(&["t", "т", "тонна"], "масса", 1e3),
+    unit(&["c", "ц", "центнер"], "масса", 1e2),
+    unit(&["lb", "фунт"], "масса", 0.45359237),
+    unit(&["oz", "унция"], "масса", 0.028349523125),
+    unit(&["s", "с", "сек"], "время", 1.0),
+    unit(&["ms", "мс"], "время", 1e-3),
+    unit(&["min", "мин"], "время", 60.0),
+    unit(&["h", "ч", "час"], "время", 3600.0),
+    unit(&["day", "сут", "сутки", "день"], "время", 86400.0),
+    unit(&["week", "нед", "неделя"], "время", 604800.0),
+    unit(&["year", "год"], "время", 31557600.0),
+    unit(&["m/s", "м/с"], "скорость", 1.0),
+    unit(&["km/h", "км/ч"], "скорость", 1.0 / 3.6),
+    unit(&["mph"], "скорость", 0.44704),
+    unit(&["knot", "узел"], "скорость", 1852.0 / 3600.0),
+    unit(&["m2", "м2", "м²", "m²"], "площадь", 1.0),
+    unit(&["km2", "км2", "км²", "km²"], "площадь", 1e6),
+    unit(&["dm2", "дм2", "дм²"], "площадь", 1e-2),
+    unit(&["cm2", "см2", "см²", "cm²"], "площадь", 1e-4),
+    unit(&["mm2", "мм2", "мм²", "mm²"], "площадь", 1e-6),
+    unit(&["ha", "га"], "площадь", 1e4),
+    unit(&["a", "ар", "сотка"], "площадь", 1e2),
+    unit(&["m3", "м3", "м³", "m³"], "объём", 1.0),
+    unit(&["dm3", "дм3", "дм³"], "объём", 1e-3),
+    unit(&["cm3", "см3", "см³", "cm³"], "объём", 1e-6),
+    unit(&["mm3", "мм3", "мм³"], "объём", 1e-9),
+    unit(&["l", "л", "литр"], "объём", 1e-3),
+    unit(&["ml", "мл"], "объём", 1e-6),
+    unit(&["gal", "галлон"], "объём", 3.785411784e-3),
+    unit(&["j", "дж", "джоуль"], "энергия", 1.0),
+    unit(&["kj", "кдж"], "энергия", 1e3),
+    unit(&["MJ", "МДж"], "энергия", 1e6),
+    unit(&["cal", "кал"], "энергия", 4.184),
+    unit(&["kcal", "ккал"], "энергия", 4184.0),
+    unit(&["wh", "вт*ч", "вт·ч"], "энергия", 3600.0),
+    unit(&["kwh", "квт*ч", "квт·ч"], "энергия", 3.6e6),
+    unit(&["ev", "эв"], "энергия", 1.602176634e-19),
+    unit(&["w", "вт", "ватт"], "мощность", 1.0),
+    unit(&["mW", "мВт", "милливатт"], "мощность", 1e-3),
+    unit(&["kw", "квт", "киловатт"], "мощность", 1e3),
+    unit(&["MW", "МВт", "мегаватт"], "мощность", 1e6),
+    unit(&["hp", "л.с."], "мощность", 735.49875),
+    unit(&["pa", "па"], "давление", 1.0),
+    unit(&["kpa", "кпа"], "давление", 1e3),
+    unit(&["MPa", "МПа"], "давление", 1e6),
+    unit(&["bar", "бар"], "давление", 1e5),
+    unit(&["atm", "атм"], "давление", 101325.0),
+    unit(&["mmhg", "мм рт. ст.", "мм.рт.ст."], "давление", 133.322387415),
+    unit(&["n", "н"], "сила", 1.0),
+    unit(&["kn", "кн"], "сила", 1e3),
+    unit(&["kgf", "кгс"], "сила", 9.80665),
+    unit(&["rad", "рад"], "угол", 1.0),
+    unit(&["deg", "°", "град", "градус"], "угол", std::f64::consts::PI / 180.0),
+    unit(&["b", "bit", "бит"], "информация", 1.0),
+    unit(&["B", "byte", "Б", "байт"], "информация", 8.0),
+    unit(&["kb", "kbit", "кбит", "килобит"], "информация", 1024.0),
+    unit(&["kB", "KB", "кБ", "КБ", "килобайт"], "информация", 8192.0),
+    unit(&["Mb", "Mbit", "Мбит", "мегабит"], "информация", 1048576.0),
+    unit(&["MB", "МБ", "мегабайт"], "информация", 8388608.0),
+    unit(&["Gb", "Gbit", "Гбит", "гигабит"], "информация", 1073741824.0),
+    unit(&["GB", "ГБ", "гигабайт"], "информация", 8589934592.0),
+];
+
+#[derive(Clone, Copy)]
+enum Temperature {
+    Celsius,
+    Fahrenheit,
+    Kelvin,
+}
+
+// Одиночные C и K - заглавные символы шкал, строчные c и к остаются центнером и приставкой
+fn temperature(name: &str) -> Option<Temperature> {
+    match compact(name).as_str() {
+        "C" | "С" | "℃" => return Some(Temperature::Celsius),
+        "F" | "℉" => return Some(Temperature::Fahrenheit),
+        "K" | "К" => return Some(Temperature::Kelvin),
+        _ => {}
+    }
+    match normalize(name).as_str() {
+        "°c" | "c°" | "celsius" | "цельсий" | "°с" => Some(Temperature::Celsius),
+        "°f" | "f" | "fahrenheit" | "фаренгейт" => Some(Temperature::Fahrenheit),
+        "kelvin" | "кельвин" => Some(Temperature::Kelvin),
+        _ => None,
+    }
+}
+
+fn to_kelvin(value: f64, scale: Temperature) -> f64 {
+    match scale {
+        Temperature::Celsius => value + 273.15,
+        Temperature::Fahrenheit => (value - 32.0) * 5.0 / 9.0 + 273.15,
+        Temperature::Kelvin => value,
+    }
+}
+
+fn from_kelvin(value: f64, scale: Temperature) -> f64 {
+    match scale {
+        Temperature::Celsius => value - 273.15,
+        Temperature::Fahrenheit => (value - 273.15) * 9.0 / 5.0 + 32.0,
+        Temperature::Kelvin => value,
+    }
+}
+
+fn compact(name: &str) -> String {
+    name.trim().replace(' ', "")
+}
+
+fn normalize(name: &str) -> String {
+    compact(name).to_lowercase()
+}
+
+fn find_unit(name: &str) -> Option<&'static Unit> {
+    let exact = compact(name);
+    let lowercase = exact.to_lowercase();
+    let find = |matches: &dyn Fn(&str) -> bool| {
+        UNITS
+            .iter()
+            .find(|unit| unit.names.iter().any(|candidate| matches(candidate)))
+    };
+    find(&|candidate| compact(candidate) == exact).or_else(|| {
+        find(&|candidate| !candidate.chars().any(char::is_uppercase) && compact(candidate) == lowercase)
+    })
+}
+
+/// Переводит значение между единицами одной величины. Температуры - по шкалам.
+pub fn convert(value: f64, from: &str, to: &str) -> Result<String> {
+    match (temperature(from), temperature(to)) {
+        (Some(from_scale), Some(to_scale)) => {
+            let result = from_kelvin(to_kelvin(value, from_scale), to_scale);
+            return Ok(format!("{} {} = {} {}", format_float(value), from, format_float(result), to));
+        }
+        (Some(_), None) | (None, Some(_)) => {
+            bail!("нельзя перевести {} в {}: температура переводится только по шкалам", from, to)
+        }
+        (None, None) => {}
+    }
+
+    let (Some(from_unit), Some(to_unit)) = (find_unit(from), find_unit(to)) else {
+        bail!("неизвестная единица: {}", if find_unit(from).is_none() { from } else { to });
+    };
+    if from_unit.quantity != to_unit.quantity {
+        bail!(
+            "нельзя перевести {} ({}) в {} ({})",
+            from,
+            from_unit.quantity,
+            to,
+            to_unit.quantity
+        );
+    }
+
+    let result = value * from_unit.factor / to_unit.factor;
+    Ok(format!("{} {} = {} {}", format_float(value), from, format_float(result), to))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_within_quantity() {
+        assert_eq!(convert(1.5, "km", "m").unwrap(), "1.5 km = 1500 m");
+        assert_eq!(convert(1.0, "миля", "км").unwrap(), "1 миля = 1.609344 км");
+        assert_eq!(convert(2.0, "ч", "мин").unwrap(), "2 ч = 120 мин");
+        assert_eq!(convert(1.0, "мм рт. ст.", "Па").unwrap(), "1 мм рт. ст. = 133.322387415 Па");
+    }
+
+    #[test]
+    fn converts_temperature_scales() {
+        assert_eq!(convert(100.0, "°C", "°F").unwrap(), "100 °C = 212 °F");
+        assert_eq!(convert(0.0, "K", "°C").unwrap(), "0 K = -273.15 °C");
+    }
+
+    #[test]
+    fn prefixes_are_case_sensitive() {
+        assert_eq!(convert(1.0, "mW", "W").unwrap(), "1 mW = 0.001 W");
+        assert_eq!(convert(1.0, "MW", "W").unwrap(), "1 MW = 1000000 W");
+        assert_eq!(convert(1.0, "МВт", "кВт").unwrap(), "1 МВт = 1000 кВт");
+        assert_eq!(convert(1.0, "Mb", "MB").unwrap(), "1 Mb = 0.125 MB");
+        assert_eq!(convert(8.0, "b", "B").unwrap(), "8 b = 1 B");
+        assert!(convert(1.0, "mw", "W").is_err());
+    }
+
+    #[test]
+    fn words_and_plain_symbols_ignore_case() {
+        assert_eq!(convert(2.0, "Киловатт", "Ватт").unwrap(), "2 Киловатт = 2000 Ватт");
+        assert_eq!(convert(1.0, "KM", "M").unwrap(), "1 KM = 1000 M");
+        assert_eq!(convert(1.0, "мегабайт", "КБ").unwrap(), "1 мегабайт = 1024 КБ");
+    }
+
+    #[test]
+    fn single_letter_scales_are_temperatures() {
+        assert_eq!(convert(100.0, "C", "F").unwrap(), "100 C = 212 F");
+        assert_eq!(convert(300.0, "K", "С").unwrap(), "300 K = 26.85 С");
+        assert_eq!(convert(1.0, "c", "кг").unwrap(), "1 c = 100 кг");
+        assert!(convert(100.0, "C", "кг").is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_and_mismatched_units() {
+        assert!(convert(1.0, "парсек", "м").is_err());
+        assert!(convert(1.0, "кг", "м").is_err());
+    }
+}
@@ -1,3 +1,5 @@
+const PROTOCOL_VERSION = 1;
+
 class VoiceAssistant {
     constructor() {
         this.ws = null;
@@ -10,6 +12,7 @@ class VoiceAssistant {
         this.responseTimes = [];
         this.startTime = null;
         this.lastRequestTime = 0;
+        this.lastMessageId = 0;
         
         this.initElements();
         this.loadStats();
@@ -43,7 +46,13 @@ class VoiceAssistant {
 
     initWebSocket() {
         const protocol = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
-        const wsUrl = `${protocol}//${window.location.host}/ws`;
+        // Токен устройства можно передать один раз в адресе страницы: /?token=...
+        const params = new URLSearchParams(window.location.search);
+        if (params.has('token')) {
+            localStorage.setItem('token', params.get('token'));
+        }
+        const token = localStorage.getItem('token');
+        const wsUrl = `${protocol}//${window.location.host}/ws` + (token ? `?token=${encodeURIComponent(token)}` : '');
         
         this.connectWebSocket(wsUrl);
     }
@@ -56,6 +65,8 @@ class VoiceAssistant {
             this.serverStatusEl.textContent = 'Online';
             this.addMessage('assistant', ' Подключение установлено! Можно начинать разговор.');
             
+            // После переподключения сервер продолжит тот же разговор
+            this.send({ type: 'hello', version: PROTOCOL_VERSION, session: sessionStorage.getItem('session') });
             this.startPing();
         };
         
@@ -80,8 +91,36 @@ class VoiceAssistant {
         };
         
         this.ws.onmessage = (event) => {
-            if (event.data === 'pong') {
-                return;
+            const message = JSON.parse(event.data);
+            let text;
+            switch (message.type) {
+                case 'session':
+                    sessionStorage.setItem('session', message.id);
+                    return;
+                case 'status':
+                    if (message.status === 'stats') {
+                        console.log('Audio stats:', message.value);
+                    } else if (message.status === 'cancelled') {
+                        this.addMessage('assistant', 'Запрос отменён');
+                        this.finishRequest();
+                    }
+                    return;
+                case 'transcript':
+                    this.showTranscript(message.text);
+                    return;
+                case 'error':
+                    console.warn('Server error:', message.code);
+                    text = message.message;
+                    break;
+                case 'answer':
+                    text = message.text;
+                    break;
+                case 'mode':
+                    // Подтверждение режима уже пришло обычным ответом
+                    console.log('Mode:', message.mode);
+                    return;
+                default:
+                    return;
             }
             
             const responseTime = Date.now() - this.startTime;
@@ -91,20 +130,20 @@ class VoiceAssistant {
             this.responseTimeEl.textContent = `${averageTime}ms`;
             this.requestCountEl.textContent = this.totalRequests;
             this.saveStats();
-            this.addMessage('assistant', event.data);
-            this.recordStatus.textContent = 'Нажмите и говорите';
-            this.visualizer.classList.remove('active');
-            this.isProcessing = false;
-            this.recordBtn.disabled = false;
-            this.updateCharCounter();
+            this.addMessage('assistant', text);
+            this.finishRequest();
         };
     }
 
+    send(message) {
+        this.ws.send(JSON.stringify({ id: String(++this.lastMessageId), ...message }));
+    }
+
     startPing() {
         const interval = this.pingInterval || 30000; 
         this.pingIntervalId = setInterval(() => {
             if (this.ws && this.ws.readyState === WebSocket.OPEN) {
-                this.ws.send('ping');
+                this.send({ type: 'ping' });
             }
         }, interval);
     }
@@ -195,7 +234,21 @@ class VoiceAssistant {
                 this.sendTextMessage();
             }
         });
+        // Escape прерывает запрос, который ещё обрабатывается
+        document.addEventListener('keydown', (e) => {
+            if (e.key === 'Escape' && this.isProcessing && this.ws && this.ws.readyState === WebSocket.OPEN) {
+                this.send({ type: 'cancel' });
+            }
+        });
+
+        this.updateCharCounter();
+    }
 
+    finishRequest() {
+        this.recordStatus.textContent = 'Нажмите и говорите';
+        this.visualizer.classList.remove('active');
+        this.isProcessing = false;
+        this.recordBtn.disabled = false;
         this.updateCharCounter();
     }
 
@@ -266,7 +319,7 @@ class VoiceAssistant {
         this.sendBtn.disabled = true;
         this.recordBtn.disabled = true;
         this.startTime = Date.now();
-        this.ws.send(`text:${message}`);
+        this.send({ type: 'text', text: message });
     }
 
     loadStats() {
@@ -342,13 +395,9 @@ class VoiceAssistant {
             this.requestCountEl.textContent = this.requestCount;
             
             this.ws.send(pcmData);
-            this.ws.send(new TextEncoder().encode('END_STREAM'));
+            this.send({ type: 'audio_end' });
             
-            const userMessages = this.messages.querySelectorAll('.message.user');
-            const lastUserMessage = userMessages[userMessages.length - 1];
-            if (lastUserMessage) {
-                lastUserMessage.querySelector('.message-content p').textContent = '🎤 Голосовое сообщение отправлено';
-            }
+            this.showTranscript('Голосовое сообщение отправлено');
             
         } catch (error) {
             console.error('Ошибка обработки аудио:', error);
@@ -357,6 +406,14 @@ class VoiceAssistant {
         }
     }
 
+    showTranscript(text) {
+        const userMessages = this.messages.querySelectorAll('.message.user');
+        const lastUserMessage = userMessages[userMessages.length - 1];
+        if (lastUserMessage) {
+            lastUserMessage.querySelector('.message-content p').textContent = `🎤 ${text}`;
+        }
+    }
+
     audioBufferToPCM(audioBuffer) {
         const channelData = audioBuffer.getChannelData(0);
         const pcmData = new Int16Array(channelData.length);